@@ -0,0 +1,285 @@
+use crate::error_template::GoodreadsError;
+use crate::export::holds_to_ics;
+use crate::matching::MatchStrictness;
+use crate::notify;
+use crate::refresh::{self, CheckError, Watch};
+use crate::types::BookFormat;
+use axum::extract::Query;
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use serde_json::json;
+use std::convert::Infallible;
+use tracing::{info, warn};
+
+/// Query string for `GET /api/availability`, mirroring the share links of the web UI.
+#[derive(Debug, serde::Deserialize)]
+pub struct AvailabilityParams {
+    user_id: String,
+    // comma-separated library website ids, e.g. "50,34550"
+    libraries: String,
+    // comma-separated shelf names, defaults to the to-read shelf
+    shelves: Option<String>,
+    // comma-separated formats ("ebook,audiobook,magazine,comic"), defaults to audiobooks
+    formats: Option<String>,
+    // comma-separated Overdrive language codes, e.g. "en,es". any language when missing
+    languages: Option<String>,
+    // pre-order titles are left out unless this is false
+    exclude_preorders: Option<bool>,
+    // "exact", "normalized" or "fuzzy", defaults to fuzzy
+    matching: Option<String>,
+}
+
+/// `GET /api/availability?user_id=...&libraries=50,34550[&shelves=to-read][&formats=ebook][&languages=en,es][&exclude_preorders=false][&matching=exact]`
+///
+/// Reads the user's Goodreads shelves and checks every book at every library, the same way
+/// the Search button does. Responds with a JSON array of `LibbyBook`, in shelf order:
+///
+/// ```json
+/// [{
+///   "cover": "https://...", "title": "The Way of Kings", "author": "Brandon Sanderson",
+///   "availability": "Holdable", "is_available": false, "is_holdable": true, "libby_search_url": "https://libbyapp.com/...",
+///   "estimated_wait_days": 21, "available_copies": null, "errored_libraries": [],
+///   "ebook": null,
+///   "audiobook": { "is_available": false, "is_holdable": true, "libby_search_url": "...",
+///     "estimated_wait_days": 21, "available_copies": null },
+///   "magazine": null, "comic": null,
+///   "library_books": [{ "cover": "...", "title": "...", "author": "...",
+///     "library": "Hawaii State Public Library System", "format": "Audiobook",
+///     "is_available": false, "is_holdable": true,
+///     "libby_search_url": "https://libbyapp.com/library/hawaii/media/1234567", "title_id": "1234567",
+///     "estimated_wait_days": 21, "holds_count": 12, "owned_copies": 3,
+///     "available_copies": 0 }],
+///   "checked_at": "2024-10-14T17:02:11.123Z", "pinned_title_id": null
+/// }]
+/// ```
+///
+/// Errors are `{"error": "..."}` with 400 for a missing user id or libraries, 403 for a
+/// private Goodreads profile, 404 for an unknown user, and 502 when Goodreads or Overdrive fail.
+/// A library that fails while checking a single book doesn't fail the request; it is listed in
+/// that book's `errored_libraries` instead.
+///
+/// While the shelf's last check is recent (at most twice REFRESH_INTERVAL_SECS old), whether
+/// by an earlier request or the background refresh of an email subscription, that is answered
+/// straight away; see `checked_at`.
+pub async fn availability(Query(params): Query<AvailabilityParams>) -> Response {
+    let watch = match parse_params(params) {
+        Ok(watch) => watch,
+        Err(response) => return response,
+    };
+    if let Some(refreshed) = refresh::cached(&watch) {
+        return Json(refreshed.availability).into_response();
+    }
+    match refresh::check(&watch).await {
+        Ok(availability) => Json(availability).into_response(),
+        Err(err) => check_failure(err),
+    }
+}
+
+/// `GET /api/availability/stream`, with the same params as `/api/availability`.
+///
+/// Instead of waiting for the whole shelf, sends each `LibbyBook` as a Server-Sent Event as
+/// soon as its check finishes, so results arrive in completion order rather than shelf order.
+/// Each result is a `data:` event holding the JSON-serialized `LibbyBook`; a book whose check
+/// fails is sent as an `error` event with the message instead, and the other books keep going.
+/// A final `done` event marks the end of the shelf.
+///
+/// Problems found before any book is checked respond with the same `{"error": "..."}` bodies
+/// and status codes as `/api/availability`. Always checks live, since the point is watching
+/// the results come in.
+pub async fn availability_stream(Query(params): Query<AvailabilityParams>) -> Response {
+    let watch = match parse_params(params) {
+        Ok(watch) => watch,
+        Err(response) => return response,
+    };
+    let prepared = match refresh::prepare(&watch).await {
+        Ok(prepared) => prepared,
+        Err(err) => return check_failure(err),
+    };
+
+    let events = refresh::check_books(&watch, prepared, false)
+        .map(|(_, result)| {
+            let event = match result {
+                Ok(libby_book) => Event::default()
+                    .json_data(&libby_book)
+                    .unwrap_or_else(|err| Event::default().event("error").data(err.to_string())),
+                Err(err) => {
+                    warn!(error = %err, "Streamed availability check failed.");
+                    Event::default().event("error").data(err)
+                }
+            };
+            Ok::<_, Infallible>(event)
+        })
+        .chain(stream::once(async {
+            Ok(Event::default().event("done").data(""))
+        }));
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// `GET /api/availability.ics`, with the same params as `/api/availability`.
+///
+/// An iCalendar feed to subscribe to in a calendar app: an all-day event per holdable book
+/// with a wait estimate, on today's date plus the shortest estimated wait among the libraries.
+/// Books without an estimate, available now, or not owned are left out. Errors are the same
+/// `{"error": "..."}` bodies as `/api/availability`, and recent refreshes are served the same
+/// way too, which suits calendar apps polling the feed.
+pub async fn availability_ics(Query(params): Query<AvailabilityParams>) -> Response {
+    let watch = match parse_params(params) {
+        Ok(watch) => watch,
+        Err(response) => return response,
+    };
+    let checks = match refresh::cached(&watch) {
+        Some(refreshed) => Ok(refreshed.availability),
+        None => refresh::check(&watch).await.map_err(check_failure),
+    };
+    match checks {
+        Ok(availability) => {
+            let now = Utc::now();
+            (
+                [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+                holds_to_ics(&availability, now.date_naive(), now),
+            )
+                .into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Query string for `GET /api/confirm` and `GET /api/unsubscribe`, the links in the
+/// confirmation email and at the bottom of every availability email.
+#[derive(Debug, serde::Deserialize)]
+pub struct SubscriptionParams {
+    id: String,
+}
+
+/// `GET /api/confirm?id=...`
+///
+/// Starts the availability emails for a subscription, from the link in its confirmation email.
+/// Responds in plain text, like unsubscribe.
+pub async fn confirm(Query(params): Query<SubscriptionParams>) -> Response {
+    if notify::confirm(params.id.trim()) {
+        info!("Confirmed availability emails.");
+        (
+            StatusCode::OK,
+            "Confirmed, you'll get an email when a book becomes available.",
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            "That confirmation link has expired or was already used, sign up again.",
+        )
+            .into_response()
+    }
+}
+
+/// `GET /api/unsubscribe?id=...`
+///
+/// Stops the availability emails for one subscription. Responds in plain text, since it's
+/// opened from an email client rather than called by code.
+pub async fn unsubscribe(Query(params): Query<SubscriptionParams>) -> Response {
+    if notify::unsubscribe(params.id.trim()) {
+        info!("Unsubscribed from availability emails.");
+        (
+            StatusCode::OK,
+            "Unsubscribed, you won't get any more emails.",
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            "No subscription with that id, it may already be gone.",
+        )
+            .into_response()
+    }
+}
+
+// Validates the params, turning them into the key background refreshes are kept under.
+fn parse_params(params: AvailabilityParams) -> Result<Watch, Response> {
+    let website_ids = split_list(&params.libraries);
+    if params.user_id.trim().is_empty() || website_ids.is_empty() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "user_id and libraries are required",
+        ));
+    }
+    let shelves = params
+        .shelves
+        .as_deref()
+        .map(split_list)
+        .unwrap_or_default();
+    let formats = params
+        .formats
+        .as_deref()
+        .map(|formats| {
+            split_list(formats)
+                .iter()
+                .filter_map(|format| BookFormat::from_overdrive_type_id(format))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let languages = params
+        .languages
+        .as_deref()
+        .map(split_list)
+        .unwrap_or_default();
+    let exclude_preorders = params.exclude_preorders.unwrap_or(true);
+    let strictness = params
+        .matching
+        .as_deref()
+        .and_then(MatchStrictness::from_param)
+        .unwrap_or_default();
+    info!(
+        user_id = params.user_id,
+        libraries = ?website_ids,
+        shelves = ?shelves,
+        formats = ?formats,
+        languages = ?languages,
+        exclude_preorders = exclude_preorders,
+        strictness = ?strictness,
+        "Headless availability check."
+    );
+    Ok(Watch::new(
+        &params.user_id,
+        shelves,
+        website_ids,
+        formats,
+        languages,
+        exclude_preorders,
+        strictness,
+    ))
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// The error response every endpoint sends when the shelves or libraries can't be checked.
+fn check_failure(err: CheckError) -> Response {
+    match err {
+        CheckError::Goodreads(GoodreadsError::PrivateProfile) => {
+            error_response(StatusCode::FORBIDDEN, "Goodreads profile is private")
+        }
+        CheckError::Goodreads(GoodreadsError::NotFound) => {
+            error_response(StatusCode::NOT_FOUND, "Goodreads user not found")
+        }
+        err => upstream_failure(err),
+    }
+}
+
+fn upstream_failure(err: impl std::fmt::Display) -> Response {
+    warn!(error = %err, "Headless availability check failed upstream.");
+    error_response(StatusCode::BAD_GATEWAY, &err.to_string())
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
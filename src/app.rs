@@ -1,532 +1,427 @@
+use futures::future::{abortable, AbortHandle};
 use futures::{stream::FuturesUnordered, StreamExt};
+use std::time::Duration;
 use std::{future::Future, pin::Pin};
 
 #[cfg(feature = "ssr")]
 use tracing::info;
 
-#[cfg(feature = "ssr")]
-use tokio;
-
-use std::time::Instant;
-
-use crate::error_template::{AppError, ErrorTemplate};
+use crate::error_template::{AppError, AvailabilityError, ErrorTemplate, GoodreadsError};
+use crate::export;
+use crate::goodreads;
+use crate::matching::MatchStrictness;
+use crate::storage;
+use crate::types::{
+    BookAvailability, BookFormat, GoodreadsBook, HooplaTitle, LibbyBook, Library, LibraryBranch,
+    SearchLibrary, COVER_PLACEHOLDER, DEFAULT_SHELF,
+};
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
-use reqwest::Client;
-use scraper::{Html, Selector};
-use serde_json::Value;
-use urlencoding::encode;
+use chrono::{DateTime, NaiveDate, Utc};
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg(feature = "ssr")]
+use crate::cache::TtlCache;
+#[cfg(feature = "ssr")]
+use crate::config::config;
+#[cfg(feature = "ssr")]
+use crate::lookup;
+#[cfg(feature = "ssr")]
+use crate::notify;
+#[cfg(feature = "ssr")]
+use crate::telemetry::ServerFnTimer;
+#[cfg(feature = "ssr")]
+use std::sync::OnceLock;
+use wasm_bindgen::JsValue;
 
-pub enum BookAvailability {
-    Available,
-    Holdable,
-    NotOwned,
-}
+// user id -> "title|author|library" -> the status it had after that user's last search
+type KnownStatuses = HashMap<String, HashMap<String, BookAvailability>>;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+// user id -> "title|author" -> the Overdrive title id that user pinned the book to
+type Pins = HashMap<String, HashMap<String, String>>;
 
-pub struct GoodreadsBook {
-    cover: String,
-    title: String,
-    author: String,
-    // date_added: String,
+// the pins this browser saved. Only call from effects or event handlers, like storage::load
+fn load_pins() -> Pins {
+    storage::load(storage::PINS_KEY)
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct LibbyLibraryBook {
-    cover: String,
-    title: String,
-    author: String,
-    is_available: bool,
-    is_holdable: bool,
-    // we don't track is_owned directly, because we can infer it from is_available and is_holdable
-    libby_search_url: String,
+fn pin_key(book: &GoodreadsBook) -> String {
+    format!("{}|{}", book.title, book.author)
 }
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct LibbyBook {
-    cover: String,
-    title: String,
-    author: String,
-    is_available: bool,
-    is_holdable: bool,
-    // we don't track is_owned directly, because we can infer it from is_available and is_holdable
-    libby_search_url: String,
-    library_books: Vec<LibbyLibraryBook>,
-}
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
-pub struct SearchLibrary {
-    system_name: String,    // Hawaii State Public Library System
-    website_id: String,     // 50
-    fulfillment_id: String, // hawaii
-    name: String,           // Hawaii Kai Library
-    branch_count: i32,
+
+// a random id tying a search's checks together on the server, see `finish_search`
+fn new_search_id() -> String {
+    format!(
+        "{:x}{:x}",
+        js_sys::Date::now() as u64,
+        (js_sys::Math::random() * 1e15) as u64
+    )
 }
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
-pub struct Library {
-    search_library: SearchLibrary,
 
-    system_id: String,          // hawaii
-    libby_base_url: String,     // https://libbyapp.com/library/hawaii
-    overdrive_base_url: String, // https://thunder.api.overdrive.com/v2/libraries/hawaii
+/// How long the user id box has to sit still before we look the user up.
+const USER_ID_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long the library search box has to sit still before we search.
+const LIBRARY_SEARCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Shortest library search we send to the Libby autocomplete endpoint.
+const MIN_LIBRARY_SEARCH_LEN: usize = 3;
+
+/// How many books the browser checks at the same time, unless the user picks otherwise.
+const DEFAULT_CHECK_CONCURRENCY: usize = 5;
+
+/// Bounds of the concurrency input, so nobody floods Overdrive or stalls at zero.
+const CHECK_CONCURRENCY_RANGE: std::ops::RangeInclusive<usize> = 1..=20;
+
+/// The least time between two progress announcements to screen readers while a search runs.
+const PROGRESS_ANNOUNCE_INTERVAL_MS: f64 = 5000.0;
+
+/// How long saved search results are restored on reload before they're considered stale.
+const SAVED_AVAILABILITY_TTL_MS: f64 = 12.0 * 60.0 * 60.0 * 1000.0;
+
+/// The columns BookTable can sort on, as stored in its `sort_by` signal. "smart" isn't a
+/// column but a ranking, see `smart_sort_key`.
+const SORT_COLUMNS: [&str; 9] = [
+    "cover",
+    "title",
+    "author",
+    "date_added",
+    "avg_rating",
+    "publisher",
+    "publication_year",
+    "availability",
+    "smart",
+];
+
+/// Languages offered in the language filter, as (Overdrive language code, label).
+const OVERDRIVE_LANGUAGES: [(&str, &str); 12] = [
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("de", "German"),
+    ("it", "Italian"),
+    ("pt", "Portuguese"),
+    ("zh", "Chinese"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("ru", "Russian"),
+    ("ar", "Arabic"),
+    ("vi", "Vietnamese"),
+];
+
+/// The shelves every Goodreads account has, offered as one-click toggles.
+const STANDARD_SHELVES: [&str; 3] = ["to-read", "currently-reading", "read"];
+
+/// A finished search as saved in local storage, so reloading the page doesn't search again.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedAvailability {
+    // the user, shelves, libraries and formats searched, see `availability_key` in HomePage
+    key: String,
+    // ms since the epoch
+    checked_at: f64,
+    availability: Vec<LibbyBook>,
 }
 
 #[derive(Params, PartialEq)]
 struct PageParams {
     user_id: String,
     libraries: String,
+    // e.g. "ebook,audiobook", optional so links shared before formats existed still work
+    formats: Option<String>,
+    // "exact", "normalized" or "fuzzy", see MatchStrictness
+    matching: Option<String>,
 }
 
 #[server(GetGoodreadsBooks, "/goodreads-books")]
+#[cfg_attr(
+    feature = "ssr",
+    tracing::instrument(
+        skip_all,
+        fields(user_id = %user_id, shelves = ?shelves, book_count = tracing::field::Empty)
+    )
+)]
 pub async fn get_goodreads_books(
     user_id: String,
-    shelf: String,
-) -> Result<Vec<GoodreadsBook>, ServerFnError> {
-    let start = Instant::now();
-
-    let books = Arc::new(Mutex::new(Vec::new()));
-    // URL of the user's to-read shelf
-    // print=true here gives us a simpler webpage to parse
-    // order=d sorts by descending
-    // sort=date_added sorts by the order the books were added
-    // TODO: get per_page to work. right now i always get 20
-    // per_page=500 gives us 500 books at once. we could do more, but probably not necessary
-    let url = format!(
-        "https://goodreads.com/review/list/{}?print=true&shelf={}",
-        user_id, shelf
-    );
-    info!(
-        user_id = user_id,
-        shelf = shelf,
-        url = url,
-        "Fetching initial page."
-    );
-
-    // Parse the HTML document
-    let mut last_page = 1;
-
-    {
-        let client = Client::new();
-        let response = client.get(&url).send().await?.text().await?;
-        let original_html = Html::parse_document(&response);
-        info!(user_id = user_id, "Parsed html successfully.");
-        // check for the `id=privateProfile` div, which indicates we won't be able to see any books
-        let private_profile_selector = Selector::parse("#privateProfile").unwrap();
-        if original_html
-            .select(&private_profile_selector)
-            .next()
-            .is_some()
-        {
-            return Err(ServerFnError::ServerError("Private profile".to_string()));
-        }
-        last_page = {
-            // get the total number of pages
-            let pagination_selector = Selector::parse("#reviewPagination a").unwrap();
-
-            // Find the highest number in the pagination links
-            let last_page = original_html
-                .select(&pagination_selector)
-                .filter_map(|element| element.text().collect::<String>().parse::<u32>().ok())
-                .max()
-                .unwrap_or(1); // If there are no pagination links, there is only one page
-
-            // in rust, the last expression without a semicolon is implicitly returned
-            last_page
-        };
-    }
+    shelves: Vec<String>,
+) -> Result<Vec<GoodreadsBook>, ServerFnError<GoodreadsError>> {
+    let _timer = ServerFnTimer::start("get_goodreads_books");
+    metrics::counter!("libbyreads_searches_total").increment(1);
+    let books = lookup::goodreads_books(&user_id, &shelves).await?;
+    tracing::Span::current().record("book_count", books.len());
+    Ok(books)
+}
 
-    let initial_page_duration = start.elapsed();
-    info!(
-        user_id = user_id,
-        total_pages = last_page,
-        duration_s = initial_page_duration.as_secs_f32(),
-        "Parsed number of pages from initial page."
-    );
-    // Create async tasks for each page
-    let mut tasks = vec![];
-    for page_number in 1..=last_page {
-        let books = Arc::clone(&books); // Clone the Arc for each task
-        let client = Client::new();
-        let page_url = format!("{}&page={}", url, page_number);
-        info!(
-            user_id = user_id,
-            url = page_url,
-            "Fetching Goodreads books."
-        );
+#[server(GetGoodreadsShelves, "/goodreads-shelves")]
+pub async fn get_goodreads_shelves(
+    user_id: String,
+) -> Result<Vec<String>, ServerFnError<GoodreadsError>> {
+    let _timer = ServerFnTimer::start("get_goodreads_shelves");
+    Ok(lookup::goodreads_shelves(&user_id).await?)
+}
 
-        // Spawn a new async task to fetch and parse the page
-        let task = tokio::task::spawn(async move {
-            if let Ok(response) = client.get(&page_url).send().await {
-                if let Ok(text) = response.text().await {
-                    let document = Html::parse_document(&text);
-
-                    // i just looked at the HTML directly to determine these selectors
-                    let book_rows_selector = Selector::parse("tr.bookalike.review").unwrap();
-                    let cover_selector = Selector::parse("td.field.cover img").unwrap();
-                    let title_selector = Selector::parse("td.field.title a").unwrap();
-                    let author_selector = Selector::parse("td.field.author a").unwrap();
-                    // let date_added_selector = Selector::parse("td.field.date_added span").unwrap();
-
-                    // Loop through each book row
-                    for book_row in document.select(&book_rows_selector) {
-                        // Get cover image
-                        let cover_element = book_row.select(&cover_selector).next().unwrap();
-                        let cover = cover_element.value().attr("src").unwrap().to_string();
-
-                        // Get title
-                        let title_element = book_row.select(&title_selector).next().unwrap();
-                        // Remove the span with the class darkGreyText, which Goodreads sometimes adds
-                        // e.g. A Darker Shade of Magic <span class="darkGreyText">(Shades of Magic, #1)</span>
-                        // should become A Darker Shade of Magic (Shades of Magic, #1)
-                        // let title = title_element
-                        //     .text()
-                        //     .collect::<Vec<_>>()
-                        //     .join("")
-                        //     .trim()
-                        //     .to_string();
-
-                        let title = title_element
-                            .children() // Get the child nodes of the <a> tag
-                            .filter(|node| node.value().is_text()) // Filter to get only the text nodes (ignoring <span>)
-                            .map(|node| node.value().as_text().unwrap().trim()) // Extract and trim the text
-                            .collect::<Vec<_>>() // Collect the text parts
-                            .join(" "); // Join them into a single string
-
-                        // Get author
-                        let author_element = book_row.select(&author_selector).next().unwrap();
-                        let author = author_element.inner_html().trim().to_string();
-                        // Get date added
-                        // let date_added_element =
-                        //     book_row.select(&date_added_selector).next().unwrap();
-                        // let date_added = date_added_element.inner_html().trim().to_string();
-
-                        // Create a book struct
-                        let book = GoodreadsBook {
-                            cover,
-                            title,
-                            author,
-                            // date_added,
-                        };
+#[server(GetStorygraphBooks, "/storygraph-books")]
+pub async fn get_storygraph_books(username: String) -> Result<Vec<GoodreadsBook>, ServerFnError> {
+    let _timer = ServerFnTimer::start("get_storygraph_books");
+    Ok(lookup::storygraph_books(&username).await?)
+}
 
-                        // Add the book to the shared vector
-                        let mut books_guard = books.lock().unwrap();
-                        books_guard.push(book);
-                    }
-                }
-            }
+#[server(GetLibbyAvailability, "/libby-availability")]
+#[cfg_attr(
+    feature = "ssr",
+    tracing::instrument(
+        skip_all,
+        fields(
+            title = %book.title,
+            library_count = libraries.len(),
+            format_count = formats.len(),
+            languages = ?languages,
+            exclude_preorders = exclude_preorders,
+            strictness = ?strictness,
+            pinned_title_id = ?pinned_title_id,
+            bypass_cache = bypass_cache,
+            library_book_count = tracing::field::Empty,
+        )
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_libby_availability(
+    book: GoodreadsBook,
+    libraries: Vec<Library>,
+    formats: Vec<BookFormat>,
+    // Overdrive language codes to limit the search to, e.g. ["en", "es"]. empty for any
+    languages: Vec<String>,
+    // leave out pre-order titles, which look holdable but can't be borrowed yet
+    exclude_preorders: bool,
+    // how closely an Overdrive title and author have to match the book's
+    strictness: MatchStrictness,
+    // the Overdrive title id the user pinned this book to, looked up instead of searched for.
+    // anything but digits is ignored
+    pinned_title_id: Option<String>,
+    // search Overdrive again even if the same search was cached, e.g. for a re-check
+    bypass_cache: bool,
+    // the browser's id for the search this book is part of, tallied for `finish_search`
+    search_id: Option<String>,
+) -> Result<LibbyBook, ServerFnError<AvailabilityError>> {
+    let _timer = ServerFnTimer::start("get_libby_availability");
+    metrics::counter!("libbyreads_books_checked_total").increment(1);
+    let pinned_title_id = pinned_title_id
+        .filter(|title_id| !title_id.is_empty() && title_id.chars().all(|c| c.is_ascii_digit()));
+    let checked = lookup::libby_availability(
+        &book,
+        &libraries,
+        &formats,
+        &languages,
+        exclude_preorders,
+        strictness,
+        pinned_title_id.as_deref(),
+        bypass_cache,
+    )
+    .await;
+    // tallied here, where the result is known, so nothing the browser sends can skew it
+    let availability = checked
+        .as_ref()
+        .ok()
+        .map(|libby_book| libby_book.availability);
+    metrics::counter!("libbyreads_search_books_total", "result" => match_result(availability))
+        .increment(1);
+    if let Some(search_id) = search_id.filter(|search_id| is_search_id(search_id)) {
+        search_tallies().update(search_id, |(tally, library_count)| {
+            tally.add(availability);
+            *library_count = (*library_count).max(libraries.len());
         });
-        tasks.push(task);
-    }
-
-    // Await all tasks
-    for task in tasks {
-        task.await?;
     }
+    let libby_book = checked?;
+    tracing::Span::current().record("library_book_count", libby_book.library_books.len());
+    Ok(libby_book)
+}
 
-    let books: std::sync::MutexGuard<'_, Vec<GoodreadsBook>> = books.lock().unwrap();
-    let duration = start.elapsed();
-    info!(
-        user_id = user_id,
-        initial_page_load_time=?initial_page_duration,
-        all_pages_load_time=?duration,
-        total_pages=last_page,
-        total_books=books.len(),
-        "Finished fetching all Goodreads pages."
-    );
-    Ok(books.clone())
+/// Hoopla search results by (title, author). An `Err` is a search that failed.
+type HooplaResults = HashMap<(String, String), Result<Option<HooplaTitle>, String>>;
+
+/// Looks a book up in Hoopla's catalog. Hoopla has one catalog for every subscribing library,
+/// so there's no library to pass; whether the user can borrow from it is up to their
+/// libraries' "Hoopla" toggles. `None` when Hoopla has no matching title.
+#[server(GetHooplaAvailability, "/hoopla-availability")]
+#[cfg_attr(
+    feature = "ssr",
+    tracing::instrument(skip_all, fields(title = %book.title, found = tracing::field::Empty))
+)]
+pub async fn get_hoopla_availability(
+    book: GoodreadsBook,
+) -> Result<Option<HooplaTitle>, ServerFnError> {
+    let _timer = ServerFnTimer::start("get_hoopla_availability");
+    let hoopla_title = lookup::hoopla_title(&book).await?;
+    tracing::Span::current().record("found", hoopla_title.is_some());
+    Ok(hoopla_title)
 }
 
-#[server(GetGoodreadsShelves, "/goodreads-shelves")]
-pub async fn get_goodreads_shelves(user_id: String) -> Result<Vec<String>, ServerFnError> {
-    info!(user_id = user_id, "Fetching Goodreads shelves.");
-    let url = format!("https://goodreads.com/review/list/{}", user_id);
-    let client = Client::new();
-    let response = client.get(&url).send().await?.text().await?;
-    let document = Html::parse_document(&response);
-    let shelf_selector = Selector::parse(".userShelf a").unwrap();
-    // Create an empty vector to hold the shelves
-    let mut shelves = Vec::new();
-
-    // Iterate over each element that matches the selector
-    for element in document.select(&shelf_selector) {
-        // Get the href attribute
-        if let Some(href) = element.value().attr("href") {
-            // Split on "shelf=" and get the part after it
-            if let Some(shelf_name) = href.split("shelf=").nth(1) {
-                // Split on any query parameters (in case there are any)
-                let shelf_name_cleaned = shelf_name
-                    .split('&')
-                    .next()
-                    .unwrap_or(shelf_name)
-                    .to_lowercase();
-                // Push the cleaned shelf name to the vector
-                shelves.push(shelf_name_cleaned.to_string());
-            }
-        }
+/// Signs an email address up for a message whenever a book on these shelves becomes available
+/// at one of these libraries, checked in the background every NOTIFY_INTERVAL_SECS. Nothing is
+/// checked or sent until the link in the confirmation email is opened.
+#[server(SubscribeToNotifications, "/subscribe-to-notifications")]
+#[cfg_attr(
+    feature = "ssr",
+    tracing::instrument(skip_all, fields(user_id = %user_id, library_count = library_website_ids.len()))
+)]
+pub async fn subscribe_to_notifications(
+    email: String,
+    user_id: String,
+    shelves: Vec<String>,
+    library_website_ids: Vec<String>,
+    formats: Vec<BookFormat>,
+) -> Result<(), ServerFnError> {
+    let _timer = ServerFnTimer::start("subscribe_to_notifications");
+    if config().smtp.is_none() {
+        return Err(ServerFnError::ServerError(
+            "Email notifications aren't set up on this server".to_string(),
+        ));
+    }
+    let email = email.trim().to_string();
+    if !crate::smtp::is_valid_address(&email) {
+        return Err(ServerFnError::ServerError(
+            "That doesn't look like an email address".to_string(),
+        ));
     }
-    if !shelves.contains(&"all".to_string()) {
-        shelves.insert(0, "all".to_string());
+    if user_id.trim().is_empty() || library_website_ids.is_empty() {
+        return Err(ServerFnError::ServerError(
+            "Pick a Goodreads user and at least one library first".to_string(),
+        ));
+    }
+    let ip = leptos_axum::extract::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .await
+        .ok()
+        .map(|axum::extract::ConnectInfo(addr)| addr.ip());
+    if let Err(err) = notify::subscribe(
+        email,
+        user_id.trim().to_string(),
+        shelves,
+        library_website_ids,
+        formats,
+        ip,
+    )
+    .await
+    {
+        return Err(ServerFnError::ServerError(err.to_string()));
     }
     info!(
-        shelves = ?shelves,
         user_id = user_id,
-        "Finished fetching Goodreads shelves."
+        "Sent an availability email confirmation."
     );
-    Ok(shelves)
+    Ok(())
 }
 
-#[server(GetLibbyAvailability, "/libby-availability")]
-pub async fn get_libby_availability(
-    book: GoodreadsBook,
-    libraries: Vec<Library>,
-) -> Result<LibbyBook, ServerFnError> {
-    // TODO: search all configured libraries concurrently for each book
-    let client = Client::new();
-    let mut libby_library_books = Vec::new();
-    let query = format!("{} {}", book.title, book.author);
-    let url_safe_query = encode(&query);
-
-    for library in &libraries {
-        let libby_search_url: String = format!(
-            "{}/search/query-{}/page-1",
-            library.libby_base_url, url_safe_query
-        );
-        // TODO: make these formats configurable via leptos multiselect dropdown
-        // let format_str: String = "format=ebook-overdrive,ebook-media-do,ebook-overdrive-provisional,audiobook-overdrive,audiobook-overdrive-provisional,magazine-overdrive".to_string();
-        let format_str: String =
-            "format=audiobook-overdrive,audiobook-overdrive-provisional".to_string();
-        let overdrive_url = format!(
-            "{}/media?query={}&{}&perPage=24&page=1&truncateDescription=false&x-client-id=dewey",
-            library.overdrive_base_url, url_safe_query, format_str,
-        );
+/// How long a search's tally waits for `finish_search` before it's dropped, e.g. when the search
+/// was cancelled or the tab closed.
+#[cfg(feature = "ssr")]
+const SEARCH_TALLY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How a search's books came out, logged as its "Search match rate." so a drop in match
+/// quality shows up without anyone having to report it.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Default)]
+pub struct MatchTally {
+    matched: usize,
+    not_found: usize,
+    unknown: usize,
+}
+
+#[cfg(feature = "ssr")]
+impl MatchTally {
+    /// Counts one book, `None` being a book whose check failed outright.
+    pub fn add(&mut self, availability: Option<BookAvailability>) {
+        match availability {
+            Some(BookAvailability::Available | BookAvailability::Holdable) => self.matched += 1,
+            Some(BookAvailability::NotOwned) => self.not_found += 1,
+            Some(BookAvailability::Unknown) | None => self.unknown += 1,
+        }
+    }
+
+    pub fn record(&self, library_count: usize) {
         info!(
-            title = book.title,
-            author = book.author,
-            library = library.search_library.system_name,
-            libby_search_url = libby_search_url,
-            "Searching for book.",
+            matched_books = self.matched,
+            not_found_books = self.not_found,
+            unknown_books = self.unknown,
+            library_count,
+            total_books = self
+                .matched
+                .saturating_add(self.not_found)
+                .saturating_add(self.unknown),
+            "Search match rate."
         );
+    }
+}
 
-        // Fetch the json from overdrive, then check the items array until we find a title that matches the book title
-
-        // Fetch the page content
-        let response = client
-            .get(overdrive_url.clone())
-            .send()
-            .await?
-            .text()
-            .await?;
-
-        // Parse the JSON document
-        let json: Value = serde_json::from_str(&response).unwrap();
-        let items = json["items"].as_array().unwrap();
-        let mut book_found_at_library = false;
-        for item in items {
-            let title_replaced = item["title"].as_str().unwrap().replace("\n", "");
-            let title: &str = title_replaced.trim();
-            let author: &str = item["firstCreatorSortName"].as_str().unwrap();
-            let is_available: bool = item["isAvailable"].as_bool().unwrap();
-            let is_holdable: bool = item["isHoldable"].as_bool().unwrap();
-            let cover: &str = item["covers"]["cover150Wide"]["href"].as_str().unwrap();
-
-            if book.title.to_lowercase().starts_with(&title.to_lowercase())
-                && author.to_lowercase() == book.author.to_lowercase()
-            {
-                let libby_library_book = LibbyLibraryBook {
-                    cover: cover.to_string(),
-                    title: title.to_string(),
-                    author: author.to_string(),
-                    is_available: is_available,
-                    is_holdable: is_holdable,
-                    libby_search_url: libby_search_url.to_string(),
-                };
-                libby_library_books.push(libby_library_book);
-                book_found_at_library = true;
-                break;
-            }
-        }
-        if !book_found_at_library {
-            info!(
-                goodreads_title = book.title,
-                goodreads_author = book.author,
-                library = library.search_library.system_name,
-                "Did not find book in libby.",
-            );
-            libby_library_books.push(LibbyLibraryBook {
-                cover: "".to_string(),
-                title: book.title.to_string(),
-                author: book.author.to_string(),
-                is_available: false,
-                is_holdable: false,
-                libby_search_url: libby_search_url.to_string(),
-            })
-        }
+// the `result` label a book's outcome is counted under
+#[cfg(feature = "ssr")]
+fn match_result(availability: Option<BookAvailability>) -> &'static str {
+    match availability {
+        Some(BookAvailability::Available | BookAvailability::Holdable) => "matched",
+        Some(BookAvailability::NotOwned) => "not_found",
+        Some(BookAvailability::Unknown) | None => "unknown",
     }
-    // find a library where `is_available` is true
-    // if not found, find a library where `is_holdable` is true
-    let mut is_available = false;
-    let mut is_holdable = false;
-    // initialize to the libby_search_url of the first library
-    let mut libby_search_url = &libby_library_books[0].libby_search_url;
-    for libby_library_book in libby_library_books.iter() {
-        if libby_library_book.is_available {
-            is_available = true;
-            libby_search_url = &libby_library_book.libby_search_url;
-            break;
-        }
-        if is_holdable == false && libby_library_book.is_holdable {
-            is_holdable = true;
-            libby_search_url = &libby_library_book.libby_search_url;
-        }
+}
+
+// search id -> the tally of its books so far, and the most libraries any was checked at
+#[cfg(feature = "ssr")]
+fn search_tallies() -> &'static TtlCache<String, (MatchTally, usize)> {
+    static TALLIES: OnceLock<TtlCache<String, (MatchTally, usize)>> = OnceLock::new();
+    TALLIES.get_or_init(|| TtlCache::new(SEARCH_TALLY_TTL))
+}
+
+// ids come from the browser, so only short alphanumeric ones are kept
+#[cfg(feature = "ssr")]
+fn is_search_id(search_id: &str) -> bool {
+    (1..=64).contains(&search_id.len()) && search_id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Logs the match rate of a search the browser ran, from the tally the server kept while
+/// checking its books. The browser only says when it's done, so it can't skew the counts.
+#[server(FinishSearch, "/finish-search")]
+pub async fn finish_search(search_id: String) -> Result<(), ServerFnError> {
+    if let Some((tally, library_count)) = search_tallies().remove(&search_id) {
+        tally.record(library_count);
     }
-    let libby_book = LibbyBook {
-        cover: book.cover.to_string(),
-        title: book.title.to_string(),
-        author: book.author.to_string(),
-        is_available: is_available,
-        is_holdable: is_holdable,
-        libby_search_url: libby_search_url.to_string(),
-        library_books: libby_library_books.clone(),
-    };
-    Ok(libby_book)
+    Ok(())
 }
 
 #[server(GetLibraries, "/libraries")]
+#[cfg_attr(
+    feature = "ssr",
+    tracing::instrument(skip_all, fields(input = %input, library_count = tracing::field::Empty))
+)]
 pub async fn get_libraries(input: String) -> Result<Vec<SearchLibrary>, ServerFnError> {
-    let client = Client::new();
-    let url = format!("https://libbyapp.com/api/locate/autocomplete/{}", input);
-    let response = client.get(&url).send().await?.text().await?;
-    let json: Value = serde_json::from_str(&response).unwrap();
-    let count = json["count"].as_i64().unwrap();
-    let total = json["total"].as_i64().unwrap();
-    info!(
-        search_input = input,
-        count = count,
-        total = total,
-        "Searching for library."
-    );
-    let branches = &json["branches"];
-    let mut libraries = Vec::<SearchLibrary>::new();
-    for branch in branches.as_array().unwrap_or(&vec![]) {
-        // find the library system for this branch
-        let system_name = branch["systems"][0]["name"].as_str().unwrap();
-        // then check if this system is already in the libraries list
-        if let Some(library) = libraries
-            .iter_mut()
-            .find(|lib| lib.system_name == system_name)
-        {
-            // if it is in the list, increment the branch count
-            library.branch_count += 1;
-        } else {
-            // if not, add it to the list
-            let fulfillment_id = branch["systems"][0]["fulfillmentId"].as_str().unwrap();
-
-            let website_id = branch["systems"][0]["websiteId"]
-                .as_i64()
-                .unwrap()
-                .to_string();
-
-            let name = branch["name"].as_str().unwrap();
-            libraries.push(SearchLibrary {
-                system_name: system_name.to_string(),
-                website_id: website_id.to_string(),
-                fulfillment_id: fulfillment_id.to_string(),
-                name: name.to_string(),
-                branch_count: 1,
-            });
-        }
-    }
-
-    let found_system_names = libraries
-        .iter()
-        .map(|lib| lib.system_name.clone())
-        .collect::<Vec<_>>()
-        .join(", ");
-    info!(
-        num_systems=libraries.len(),
-        found_system_names=?found_system_names,
-        "Found library systems via libby autocomplete."
-    );
+    let _timer = ServerFnTimer::start("get_libraries");
+    let libraries = lookup::find_libraries(&input).await?;
+    tracing::Span::current().record("library_count", libraries.len());
     Ok(libraries)
 }
 
+/// Every branch of a library system, for picking the one holds get picked up at.
+/// Availability is still checked for the whole system, which shares one catalog.
+#[server(GetLibraryBranches, "/library-branches")]
+pub async fn get_library_branches(
+    system_name: String,
+    fulfillment_id: String,
+) -> Result<Vec<LibraryBranch>, ServerFnError> {
+    let _timer = ServerFnTimer::start("get_library_branches");
+    Ok(lookup::library_branches(&system_name, &fulfillment_id).await?)
+}
+
 #[server(GetLibraryFromWebsiteId, "/library-from-website-id")]
 pub async fn get_library_from_website_id(website_id: String) -> Result<Library, ServerFnError> {
-    let system_id_url = format!(
-        "https://thunder.api.overdrive.com/v2/libraries/?websiteid={}",
-        website_id
-    );
-    let client = Client::new();
-    let library_json = client.get(&system_id_url).send().await?.text().await?;
-    let library_value: Value = serde_json::from_str(&library_json)?;
-    let system_id = library_value["items"][0]["id"].as_str().unwrap();
-    let fulfillment_id = library_value["items"][0]["fulfillmentId"].as_str().unwrap();
-    let name = library_value["items"][0]["name"].as_str().unwrap();
-    let libby_base_url = format!("https://libbyapp.com/library/{}", system_id);
-    let overdrive_base_url = format!(
-        "https://thunder.api.overdrive.com/v2/libraries/{}",
-        system_id
-    );
-    info!(
-        website_id = website_id,
-        method = "get_library_from_website_id",
-        "Found library system!"
-    );
-    let search_lib = SearchLibrary {
-        system_name: name.to_string(),
-        website_id: website_id.to_string(),
-        fulfillment_id: fulfillment_id.to_string(),
-        name: name.to_string(),
-        branch_count: 1,
-    };
-    Ok(Library {
-        search_library: search_lib,
-        system_id: system_id.to_string(),
-        libby_base_url: libby_base_url,
-        overdrive_base_url: overdrive_base_url,
-    })
+    let _timer = ServerFnTimer::start("get_library_from_website_id");
+    Ok(lookup::library_from_website_id(&website_id).await?)
 }
 
 #[server(GetLibraryFromSystemId, "/library-from-system-id")]
 pub async fn get_library_from_system_id(system_id: String) -> Result<Library, ServerFnError> {
-    let system_id_url = format!(
-        "https://thunder.api.overdrive.com/v2/libraries/{}",
-        system_id
-    );
-    let client = Client::new();
-    let library_json = client.get(&system_id_url).send().await?.text().await?;
-    let library_value: Value = serde_json::from_str(&library_json)?;
-    let name = library_value["name"].as_str().unwrap();
-    let website_id = library_value["websiteId"].as_str().unwrap();
-    let fulfillment_id = library_value["fulfillmentId"].as_str().unwrap();
-    let libby_base_url = format!("https://libbyapp.com/library/{}", system_id);
-    let overdrive_base_url = format!(
-        "https://thunder.api.overdrive.com/v2/libraries/{}",
-        system_id
-    );
-    let search_lib = SearchLibrary {
-        system_name: name.to_string(),
-        website_id: website_id.to_string(),
-        fulfillment_id: fulfillment_id.to_string(),
-        name: name.to_string(),
-        branch_count: 1,
-    };
-    info!(
-        search_lib = ?search_lib,
-        method = "get_library_from_system_id",
-        "Found library system."
-    );
-    Ok(Library {
-        search_library: search_lib,
-        system_id: system_id.to_string(),
-        libby_base_url: libby_base_url,
-        overdrive_base_url: overdrive_base_url,
-    })
+    let _timer = ServerFnTimer::start("get_library_from_system_id");
+    Ok(lookup::library_from_system_id(&system_id).await?)
 }
 
 #[component]
@@ -573,17 +468,30 @@ fn LibrarySearch(
     selected_library_website_ids: RwSignal<Vec<String>>,
 ) -> impl IntoView {
     let (search_input, set_search_input) = create_signal(String::new());
+    let pending_search = store_value(None::<leptos::leptos_dom::helpers::TimeoutHandle>);
+    // bumped for every query sent, so a slow early response can't replace a later one
+    let latest_search = store_value(0_u64);
+
+    // shown under the search box when a search comes back empty or fails
+    let search_message = create_rw_signal(None::<&'static str>);
 
     let fetch_libraries = move |input: String| {
+        latest_search.update_value(|latest| *latest += 1);
+        let search = latest_search.get_value();
         spawn_local(async move {
-            let trimmed_input = input.trim();
-            if !trimmed_input.is_empty() {
-                match get_libraries(trimmed_input.to_string()).await {
-                    Ok(libs) => {
-                        set_search_libraries.set(libs);
-                    }
-                    //TODO: what to do on error here?
-                    Err(e) => {}
+            let result = get_libraries(input).await;
+            if search != latest_search.get_value() {
+                return;
+            }
+            match result {
+                Ok(libs) => {
+                    search_message.set(libs.is_empty().then_some("No libraries found"));
+                    set_search_libraries.set(libs);
+                }
+                Err(err) => {
+                    logging::error!("Error searching libraries. {}", err);
+                    search_message.set(Some("Search failed, try again"));
+                    set_search_libraries.set(Vec::new());
                 }
             }
         });
@@ -603,8 +511,41 @@ fn LibrarySearch(
         selected_library_website_ids.set(curr_website_ids);
     };
 
+    // the selection is keyed by website id, so look that up and select the library by it
+    let system_id_input = create_rw_signal(String::new());
+    let system_id_error = create_rw_signal(None::<String>);
+    let add_library_by_system_id = move || {
+        let system_id = system_id_input.get_untracked();
+        spawn_local(async move {
+            match get_library_from_system_id(system_id.clone()).await {
+                Ok(library) => {
+                    system_id_error.set(None);
+                    system_id_input.set(String::new());
+                    add_selected_library(library.search_library);
+                }
+                Err(err) => {
+                    logging::error!("Error adding library {}. {}", system_id, err);
+                    system_id_error.set(Some(format!(
+                        "No Libby library with the ID \"{}\"",
+                        system_id.trim()
+                    )));
+                }
+            }
+        });
+    };
+
+    // only search once typing pauses, and not for a letter or two that match half the country
     create_effect(move |_| {
-        fetch_libraries(search_input.get());
+        let input = search_input.get().trim().to_string();
+        if let Some(pending) = pending_search.get_value() {
+            pending.clear();
+        }
+        if input.chars().count() < MIN_LIBRARY_SEARCH_LEN {
+            return;
+        }
+        let handle =
+            set_timeout_with_handle(move || fetch_libraries(input), LIBRARY_SEARCH_DEBOUNCE);
+        pending_search.set_value(handle.ok());
     });
 
     view! {
@@ -615,10 +556,29 @@ fn LibrarySearch(
             on:input=move |e| set_search_input(event_target_value(&e))
             style="width: 95%;" // Adjust the width as needed
         />
+        {move || search_message.get().map(|message| view! { <p>{message}</p> })}
+        // for people who already know their library's id from a libbyapp.com/library/<id> url
+        <form
+            style="display: flex; gap: 10px;"
+            on:submit=move |e| {
+                e.prevent_default();
+                add_library_by_system_id();
+            }
+        >
+            <input
+                type="text"
+                placeholder="Or a Libby library ID, e.g. hawaii"
+                prop:value=move || system_id_input.get()
+                on:input=move |e| system_id_input.set(event_target_value(&e))
+            />
+            <button type="submit" disabled=move || system_id_input.get().trim().is_empty()>"Add by ID"</button>
+        </form>
+        {move || system_id_error.get().map(|message| view! { <p style="color: #d9534f;">{message}</p> })}
         <table>
             <thead>
             <tr>
-                <th style="width: 65%; text-align: center;">"Library"</th>
+                <th style="width: 40%; text-align: center;">"Library"</th>
+                <th style="width: 25%; text-align: center;">"Location"</th>
                 <th style="width: 35%; text-align: center;">"Action"</th>
             </tr>
             </thead>
@@ -629,6 +589,7 @@ fn LibrarySearch(
                 view! {
                 <tr>
                     <td>{library.system_name.clone()}</td>
+                    <td>{library.location.clone()}</td>
                     <td>
                     {if is_selected {
                         view! {
@@ -656,28 +617,116 @@ fn LibrarySearch(
 fn DisplaySelectedLibraries(
     selected_libraries: RwSignal<Vec<Library>>,
     selected_library_website_ids: RwSignal<Vec<String>>,
+    // pickup branch per library system name, for showing alongside the results
+    selected_branches: RwSignal<HashMap<String, String>>,
+    // website ids of the libraries whose cards also work on Hoopla
+    hoopla_libraries: RwSignal<Vec<String>>,
 ) -> impl IntoView {
+    // branches of the systems whose branch picker was opened, by system name
+    let library_branches = create_rw_signal(HashMap::<String, Vec<LibraryBranch>>::new());
     let remove_selected_library = move |library: SearchLibrary| {
         let mut curr_website_ids = selected_library_website_ids.get();
         curr_website_ids.retain(|id| id != &library.website_id);
         selected_library_website_ids.set(curr_website_ids);
+        selected_branches.update(|branches| {
+            branches.remove(&library.system_name);
+        });
+        hoopla_libraries.update(|ids| ids.retain(|id| id != &library.website_id));
+    };
+    let load_branches = move |library: SearchLibrary| {
+        spawn_local(async move {
+            match get_library_branches(library.system_name.clone(), library.fulfillment_id).await {
+                Ok(branches) => library_branches.update(|library_branches| {
+                    library_branches.insert(library.system_name, branches);
+                }),
+                Err(err) => logging::error!("Error fetching library branches. {}", err),
+            }
+        });
     };
 
     view! {
         <h2>"Selected Libraries"</h2>
+        // the share link and saved selection follow selected_library_website_ids, so they clear too
+        <button
+            disabled=move || selected_library_website_ids.get().is_empty()
+            on:click=move |_| {
+                selected_library_website_ids.set(Vec::new());
+                selected_branches.set(HashMap::new());
+                hoopla_libraries.set(Vec::new());
+            }
+        >"Clear all"</button>
         <table>
             <thead>
             <tr>
-                <th style="width: 65%; text-align: center;">"Library"</th>
-                <th style="width: 35%; text-align: center;">"Action"</th>
+                <th style="width: 40%; text-align: center;">"Library"</th>
+                <th style="width: 30%; text-align: center;">"Pickup Branch"</th>
+                <th style="width: 10%; text-align: center;" title="Whether this library card also works on Hoopla">"Hoopla"</th>
+                <th style="width: 20%; text-align: center;">"Action"</th>
             </tr>
             </thead>
             <tbody>
             {move || selected_libraries.get().iter().map(|library| {
                 let library_clone = library.clone();
+                let search_library = library.search_library.clone();
+                let system_name = search_library.system_name.clone();
+                let website_id = search_library.website_id.clone();
+                let has_hoopla = {
+                    let website_id = website_id.clone();
+                    move || hoopla_libraries.get().contains(&website_id)
+                };
+                let branch_picker = move || {
+                    let system_name = system_name.clone();
+                    let search_library = search_library.clone();
+                    let Some(branches) = library_branches.with(|library_branches| library_branches.get(&system_name).cloned()) else {
+                        let chosen = selected_branches.with(|chosen| chosen.get(&system_name).cloned());
+                        return view! {
+                            {chosen.map(|chosen| view! { {chosen} " " })}
+                            <button on:click=move |_| load_branches(search_library.clone())>"Choose branch"</button>
+                        }.into_view();
+                    };
+                    let chosen = selected_branches.with(|chosen| chosen.get(&system_name).cloned()).unwrap_or_default();
+                    view! {
+                        <select on:change=move |e| {
+                            let branch = event_target_value(&e);
+                            selected_branches.update(|chosen| {
+                                if branch.is_empty() {
+                                    chosen.remove(&system_name);
+                                } else {
+                                    chosen.insert(system_name.clone(), branch);
+                                }
+                            });
+                        }>
+                            <option value="" selected=chosen.is_empty()>"Any branch"</option>
+                            {branches.into_iter().map(|branch| {
+                                let label = if branch.location.is_empty() {
+                                    branch.name.clone()
+                                } else {
+                                    format!("{} ({})", branch.name, branch.location)
+                                };
+                                view! { <option value={branch.name.clone()} selected={branch.name == chosen}>{label}</option> }
+                            }).collect_view()}
+                        </select>
+                    }.into_view()
+                };
                 view! {
                 <tr>
                     <td>{library.search_library.system_name.clone()}</td>
+                    <td>{branch_picker}</td>
+                    <td style="text-align: center;">
+                        <input
+                            type="checkbox"
+                            prop:checked=has_hoopla
+                            on:change=move |e| {
+                                let checked = event_target_checked(&e);
+                                hoopla_libraries.update(|ids| {
+                                    ids.retain(|id| id != &website_id);
+                                    if checked {
+                                        ids.push(website_id.clone());
+                                    }
+                                });
+                            }
+                        />
+                    </td>
                     <td>
                         <button style="width: 100%;" on:click=move |_| {remove_selected_library(library_clone.search_library.clone());}>
                             "Remove"
@@ -691,6 +740,241 @@ fn DisplaySelectedLibraries(
     }
 }
 
+// "Shades of Magic, #1" -> ("Shades of Magic", Some(1.0)). novellas are often #1.5
+fn split_series(series: &str) -> (&str, Option<f32>) {
+    match series.rsplit_once(", #") {
+        Some((name, number)) => (name.trim(), number.trim().parse().ok()),
+        None => (series.trim(), None),
+    }
+}
+
+/// Ranks books the user can get soonest and has wanted longest first: available, then
+/// holdable by shortest wait (unknown waits last), then everything else, with the oldest
+/// `date_added` first within each of those. Books not checked yet go to the bottom.
+fn smart_sort_key(
+    book: &GoodreadsBook,
+    libby_book: Option<&LibbyBook>,
+) -> (u8, bool, Option<i64>, bool, Option<NaiveDate>) {
+    let (bucket, wait) = match libby_book {
+        Some(libby_book) if libby_book.is_available => (0, None),
+        Some(libby_book) if libby_book.is_holdable => (1, libby_book.estimated_wait_days),
+        Some(_) => (2, None),
+        None => (3, None),
+    };
+    (
+        bucket,
+        bucket == 1 && wait.is_none(),
+        wait,
+        book.date_added.is_none(),
+        book.date_added,
+    )
+}
+
+/// The Overdrive title id in a pasted Libby link (".../media/1234567") or bare id, an empty one
+/// for empty input. None if it's neither.
+fn parse_title_id(input: &str) -> Option<String> {
+    let input = input.trim();
+    let title_id = input
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(input)
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    title_id
+        .chars()
+        .all(|c| c.is_ascii_digit())
+        .then(|| title_id.to_string())
+}
+
+// e.g. "AVAILABLE (3 copies)", or just "AVAILABLE" when Overdrive doesn't say how many
+fn available_label(available_copies: Option<i64>) -> String {
+    match available_copies {
+        Some(1) => "AVAILABLE (1 copy)".to_string(),
+        Some(copies) if copies > 0 => format!("AVAILABLE ({} copies)", copies),
+        _ => "AVAILABLE".to_string(),
+    }
+}
+
+// e.g. "HOLDABLE — ~21 days", or just "HOLDABLE" when Overdrive has no estimate
+fn holdable_label(estimated_wait_days: Option<i64>) -> String {
+    match estimated_wait_days {
+        Some(days) => format!("HOLDABLE — ~{} days", days),
+        None => "HOLDABLE".to_string(),
+    }
+}
+
+// Goodreads' original year when the shelf has one, else the year of the edition Libby found
+fn publication_year(book: &GoodreadsBook, libby_book: Option<&LibbyBook>) -> Option<i32> {
+    book.publication_year
+        .or_else(|| libby_book.and_then(|libby_book| libby_book.publication_year))
+}
+
+// e.g. "Narrated by Jim Dale · 10h 12m" from Overdrive's "10:12:34", whichever we know
+fn audiobook_label(narrator: Option<&str>, duration: Option<&str>) -> Option<String> {
+    let duration = duration.map(|duration| {
+        let parts = duration
+            .split(':')
+            .map(|part| part.trim().parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>();
+        match parts.as_deref() {
+            Some([hours, minutes, _seconds]) if *hours > 0 => format!("{}h {}m", hours, minutes),
+            Some([_hours, minutes, _seconds]) => format!("{}m", minutes),
+            _ => duration.to_string(),
+        }
+    });
+    let narrator = narrator.map(|narrator| format!("Narrated by {}", narrator));
+    let label = [narrator, duration]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ");
+    (!label.is_empty()).then_some(label)
+}
+
+// e.g. "checked 2m ago", or "checked just now" under a minute
+fn checked_ago_label(checked_at: DateTime<Utc>, now_ms: f64) -> String {
+    let secs = ((now_ms as i64 - checked_at.timestamp_millis()) / 1000).max(0);
+    match secs {
+        0..=59 => "checked just now".to_string(),
+        60..=3599 => format!("checked {}m ago", secs / 60),
+        3600..=86399 => format!("checked {}h ago", secs / 3600),
+        _ => format!("checked {}d ago", secs / 86400),
+    }
+}
+
+// e.g. "47% — ~35s remaining", extrapolating the time the finished books took to the rest
+fn progress_label(done: usize, total: usize, elapsed_ms: Option<f64>) -> String {
+    if total == 0 {
+        return String::new();
+    }
+    let percent = format!("{}%", done.min(total) * 100 / total);
+    let remaining_secs = match elapsed_ms {
+        Some(elapsed_ms) if done > 0 && done < total => {
+            (elapsed_ms / done as f64 * (total - done) as f64 / 1000.0).ceil() as u64
+        }
+        _ => return percent,
+    };
+    let remaining = if remaining_secs >= 60 {
+        format!("{}m {}s", remaining_secs / 60, remaining_secs % 60)
+    } else {
+        format!("{}s", remaining_secs)
+    };
+    format!("{} — ~{} remaining", percent, remaining)
+}
+
+// e.g. a "Magazine" tag after a HOLDABLE that came from a magazine, not a book. None when the
+// overall result is a book, or there's no result worth reading
+fn non_book_badge(libby_book: &LibbyBook) -> Option<View> {
+    let best = libby_book
+        .library_books
+        .iter()
+        .find(|library_book| library_book.is_available)
+        .or_else(|| {
+            libby_book
+                .library_books
+                .iter()
+                .find(|library_book| library_book.is_holdable)
+        })?;
+    matches!(best.format, BookFormat::Magazine | BookFormat::Comic).then(|| {
+        view! {
+            <small style="margin-left: 5px; padding: 0 4px; border: 1px solid #888; border-radius: 3px;">
+                {best.format.label()}
+            </small>
+        }
+        .into_view()
+    })
+}
+
+// the Hoopla column: still pending, a link to borrow it now, not on Hoopla, or a failed search
+fn hoopla_badge(result: Option<Result<Option<HooplaTitle>, String>>) -> View {
+    match result {
+        None => view! { "..." }.into_view(),
+        Some(Ok(Some(hoopla_title))) => view! {
+            <a href={hoopla_title.hoopla_url} target="_blank" title={hoopla_title.kind}>"INSTANT"</a>
+        }
+        .into_view(),
+        Some(Ok(None)) => view! { "—" }.into_view(),
+        Some(Err(err)) => view! { <span title={err} style="color: #d9534f;">"⚠"</span> }.into_view(),
+    }
+}
+
+// the badge for one format's column: still pending, not searched, or that format's availability
+fn format_badge(libby_book: Option<&LibbyBook>, format: BookFormat) -> View {
+    let Some(libby_book) = libby_book else {
+        return view! { "..." }.into_view();
+    };
+    match libby_book.format(format) {
+        Some(availability) if availability.is_available => view! {
+            <a href={availability.libby_search_url.clone()} target="_blank">
+                {available_label(availability.available_copies)}
+            </a>
+        }
+        .into_view(),
+        Some(availability) if availability.is_holdable => view! {
+            <a href={availability.libby_search_url.clone()} target="_blank">
+                {holdable_label(availability.estimated_wait_days)}
+            </a>
+        }
+        .into_view(),
+        Some(_) => view! { "NOT OWNED" }.into_view(),
+        None => view! { "-" }.into_view(),
+    }
+}
+
+/// A column header that sorts the table by `column`, starting in `first_order` ("asc" or
+/// "desc") and flipping on each click after that. The header reports the current sort to
+/// screen readers, and its label is a focusable button that Enter and Space also toggle.
+#[component]
+fn SortHeader(
+    column: &'static str,
+    label: &'static str,
+    first_order: &'static str,
+    sort_by: ReadSignal<String>,
+    sort_order: ReadSignal<String>,
+    set_sort_by: WriteSignal<String>,
+    set_sort_order: WriteSignal<String>,
+) -> impl IntoView {
+    let toggle = move || {
+        let other_order = if first_order == "asc" { "desc" } else { "asc" };
+        let order =
+            if sort_by.get_untracked() == column && sort_order.get_untracked() == first_order {
+                other_order
+            } else {
+                first_order
+            };
+        set_sort_by(column.to_string());
+        set_sort_order(order.to_string());
+    };
+    let aria_sort = move || {
+        if sort_by.get() != column {
+            "none"
+        } else if sort_order.get() == "asc" {
+            "ascending"
+        } else {
+            "descending"
+        }
+    };
+    view! {
+        <th aria-sort=aria_sort on:click=move |_| toggle()>
+            <span
+                role="button"
+                tabindex="0"
+                on:keydown=move |e| {
+                    if matches!(e.key().as_str(), "Enter" | " ") {
+                        // Space would scroll the page otherwise
+                        e.prevent_default();
+                        toggle();
+                    }
+                }
+            >
+                {label}
+            </span>
+        </th>
+    }
+}
+
 #[component]
 fn BookTable(
     books: ReadSignal<Vec<GoodreadsBook>>,
@@ -699,41 +983,101 @@ fn BookTable(
     sort_order: ReadSignal<String>,
     set_sort_by: WriteSignal<String>,
     set_sort_order: WriteSignal<String>,
+    formats: RwSignal<Vec<BookFormat>>,
+    // statuses to show, None standing for books that haven't been checked yet
+    availability_filter: RwSignal<Vec<Option<BookAvailability>>>,
+    // only rows whose title or author contains this, ignoring case
+    search_text: RwSignal<String>,
+    // checks a single book again, e.g. one whose lookup failed
+    on_recheck: Callback<GoodreadsBook>,
+    // pins a book to an Overdrive title id, or unpins it with an empty one
+    on_pin: Callback<(GoodreadsBook, String)>,
+    group_by_series: RwSignal<bool>,
+    // pickup branch per library system name
+    branches: RwSignal<HashMap<String, String>>,
+    // Hoopla search results by (title, author), and whether any library has Hoopla at all
+    hoopla: RwSignal<HooplaResults>,
+    #[prop(into)] show_hoopla: Signal<bool>,
+    // libraries where a book became available since the last search, by (title, author)
+    newly_available: RwSignal<HashMap<(String, String), Vec<String>>>,
+    // tighter rows and thumbnail covers, to fit more books on a screen
+    compact: RwSignal<bool>,
+    // the optional Publisher and Year columns
+    show_publication: RwSignal<bool>,
 ) -> impl IntoView {
+    // (title, author) of the rows showing their per-library breakdown; kept out here because
+    // the rows are rebuilt every time a result arrives
+    let expanded_books = create_rw_signal(std::collections::HashSet::<(String, String)>::new());
     view! {
-        <table>
+        // the class names are for the stylesheet, which turns rows into cards on a phone
+        <table class="book-table" class:compact=move || compact.get()>
         <thead>
         <tr>
-        <th on:click=move |_| {
-        set_sort_by("cover".to_string());
-        set_sort_order(if sort_by.get() == "cover" && sort_order.get() == "asc" { "desc".to_string() } else { "asc".to_string() });
-        }>"Cover"</th>
-        <th on:click=move |_| {
-        set_sort_by("title".to_string());
-        set_sort_order(if sort_by.get() == "title" && sort_order.get() == "asc" { "desc".to_string() } else { "asc".to_string() });
-        }>"Title"</th>
-        <th on:click=move |_| {
-        set_sort_by("author".to_string());
-        set_sort_order(if sort_by.get() == "author" && sort_order.get() == "asc" { "desc".to_string() } else { "asc".to_string() });
-        }>"Author"</th>
-        <th on:click=move |_| {
-        set_sort_by("availability".to_string());
-        set_sort_order(if sort_by.get() == "availability" && sort_order.get() == "desc" { "asc".to_string() } else { "desc".to_string() });
-        }>"Libby Availability"</th>
+        <SortHeader column="cover" label="Cover" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        <SortHeader column="title" label="Title" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        <SortHeader column="author" label="Author" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        <SortHeader column="date_added" label="Date Added" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        // highest rated first
+        <SortHeader column="avg_rating" label="Rating" first_order="desc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        {move || show_publication.get().then(|| view! {
+        <SortHeader column="publisher" label="Publisher" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        // newest first
+        <SortHeader column="publication_year" label="Year" first_order="desc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        })}
+        <SortHeader column="availability" label="Libby Availability" first_order="asc" sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+        {move || formats.get().into_iter().map(|format| view! { <th>{format.label()}</th> }).collect_view()}
+        {move || show_hoopla.get().then(|| view! { <th>"Hoopla"</th> })}
         </tr>
         </thead>
         <tbody>
         {move || {
         let mut sorted_books = books.get().clone();
+        // filter on the full result list, the summary counts above the table stay unfiltered
+        let status_filter = availability_filter.get();
+        let availability_list = availability.get();
+        let search_text = search_text.get().trim().to_lowercase();
+        sorted_books.retain(|book| {
+            if !search_text.is_empty()
+                && !book.title.to_lowercase().contains(&search_text)
+                && !book.author.to_lowercase().contains(&search_text)
+            {
+                return false;
+            }
+            let status = availability_list
+                .iter()
+                .find(|libby_book| libby_book.is_for(book))
+                .map(|libby_book| libby_book.availability);
+            status_filter.contains(&status)
+        });
+        let libby_book_for = |book: &GoodreadsBook| availability_list.iter().find(|libby_book| libby_book.is_for(book));
+        let publisher_of = |book: &GoodreadsBook| libby_book_for(book).and_then(|libby_book| libby_book.publisher.clone());
+        let year_of = |book: &GoodreadsBook| publication_year(book, libby_book_for(book));
         sorted_books.sort_by(|a, b| {
+            // unrated books, and ones with no known publisher or year, always sink to the
+            // bottom, whichever direction we sort
+            let missing = match sort_by.get().as_str() {
+            "avg_rating" => Some((a.avg_rating.is_none(), b.avg_rating.is_none())),
+            "publisher" => Some((publisher_of(a).is_none(), publisher_of(b).is_none())),
+            "publication_year" => Some((year_of(a).is_none(), year_of(b).is_none())),
+            _ => None,
+            };
+            match missing {
+            Some((false, true)) => return std::cmp::Ordering::Less,
+            Some((true, false)) => return std::cmp::Ordering::Greater,
+            _ => {}
+            }
             let order = match sort_by.get().as_str() {
             "cover" => a.cover.cmp(&b.cover),
             "title" => a.title.cmp(&b.title),
             "author" => a.author.cmp(&b.author),
+            "date_added" => a.date_added.cmp(&b.date_added),
+            "avg_rating" => a.avg_rating.partial_cmp(&b.avg_rating).unwrap_or(std::cmp::Ordering::Equal),
+            "publisher" => publisher_of(a).map(|publisher| publisher.to_lowercase()).cmp(&publisher_of(b).map(|publisher| publisher.to_lowercase())),
+            "publication_year" => year_of(a).cmp(&year_of(b)),
             "availability" => {
                 let availability_list = availability.get();
-                let a_availability = availability_list.iter().find(|libby_book| libby_book.title == a.title && libby_book.author == a.author);
-                let b_availability = availability_list.iter().find(|libby_book| libby_book.title == b.title && libby_book.author == b.author);
+                let a_availability = availability_list.iter().find(|libby_book| libby_book.is_for(a));
+                let b_availability = availability_list.iter().find(|libby_book| libby_book.is_for(b));
                 match (a_availability, b_availability) {
                 (Some(a_libby), Some(b_libby)) => {
                 if a_libby.is_available && !b_libby.is_available {
@@ -744,6 +1088,14 @@ fn BookTable(
                 std::cmp::Ordering::Less
                 } else if !a_libby.is_holdable && b_libby.is_holdable {
                 std::cmp::Ordering::Greater
+                } else if a_libby.is_holdable {
+                // among holdable books the shortest wait comes first, unknown waits last
+                match (a_libby.estimated_wait_days, b_libby.estimated_wait_days) {
+                (Some(a_wait), Some(b_wait)) => a_wait.cmp(&b_wait),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+                }
                 } else {
                 std::cmp::Ordering::Equal
                 }
@@ -753,39 +1105,236 @@ fn BookTable(
                 (None, None) => std::cmp::Ordering::Equal,
                 }
             }
+            "smart" => smart_sort_key(a, libby_book_for(a)).cmp(&smart_sort_key(b, libby_book_for(b))),
             _ => std::cmp::Ordering::Equal,
             };
-            if sort_order.get() == "asc" {
+            let order = if sort_order.get() == "asc" {
             order
             } else {
             order.reverse()
+            };
+            // books in the same availability bucket keep a fixed A-Z order, otherwise they'd
+            // shuffle every time a streamed result re-sorts the table
+            if matches!(sort_by.get().as_str(), "availability" | "smart") {
+            order
+                .then_with(|| a.title.cmp(&b.title))
+                .then_with(|| a.author.cmp(&b.author))
+            } else {
+            order
             }
         });
+        if group_by_series.get() {
+            // pull each series together where its first book landed, in series order
+            let mut first_position = HashMap::new();
+            for (position, book) in sorted_books.iter().enumerate() {
+                if let Some((name, _)) = book.series.as_deref().map(split_series) {
+                    first_position.entry(name.to_string()).or_insert(position);
+                }
+            }
+            let mut positioned = sorted_books.into_iter().enumerate().map(|(position, book)| {
+                let key = match book.series.as_deref().map(split_series) {
+                    Some((name, number)) => (first_position[name], number.unwrap_or(f32::MAX)),
+                    None => (position, 0.0),
+                };
+                (key, book)
+            }).collect::<Vec<_>>();
+            positioned.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+            sorted_books = positioned.into_iter().map(|(_, book)| book).collect();
+        }
         sorted_books.into_iter().map(|book| {
-        let libby_book = availability.get().into_iter().find(|libby_book| libby_book.title == book.title && libby_book.author == book.author);
+        let libby_book = availability.get().into_iter().find(|libby_book| libby_book.is_for(&book));
+        let book_key = (book.title.clone(), book.author.clone());
+        let is_expanded = {
+            let book_key = book_key.clone();
+            move || expanded_books.get().contains(&book_key)
+        };
+        let library_books = libby_book.as_ref().map(|libby_book| libby_book.library_books.clone()).unwrap_or_default();
         view! {
-        <tr>
-            <td><img src={book.cover.clone()} alt="cover" /></td>
-            <td>{book.title.clone()}</td>
-            <td>{book.author.clone()}</td>
-            <td>
-            {match libby_book {
-            Some(libby_book) if libby_book.is_available => view! {
-                <a href={libby_book.libby_search_url.clone()} target="_blank">"AVAILABLE"</a>
+        <tr class="book-row">
+            // books from a Goodreads export come without covers
+            <td class="cover">
+                <img
+                    src={if book.cover.is_empty() { COVER_PLACEHOLDER.to_string() } else { book.cover.clone() }}
+                    alt={format!("Cover of {} by {}", book.title, book.author)}
+                    on:error=|e| {
+                        // a broken Goodreads link, swapped once so a missing placeholder can't loop
+                        let img = event_target::<web_sys::HtmlImageElement>(&e);
+                        if !img.src().ends_with(COVER_PLACEHOLDER) {
+                            img.set_src(COVER_PLACEHOLDER);
+                        }
+                    }
+                />
+            </td>
+            <td class="title">
+                {book.title.clone()}
+                {book.series.clone().map(|series| view! { <br /><small style="color: #888;">{series}</small> })}
+            </td>
+            <td class="author">{book.author.clone()}</td>
+            <td class="date-added">{book.date_added.map(|date| date.format("%b %d, %Y").to_string()).unwrap_or_default()}</td>
+            <td class="rating">{book.avg_rating.map(|rating| format!("{:.2}", rating)).unwrap_or_default()}</td>
+            {show_publication.get().then(|| view! {
+            <td class="publisher">{libby_book.as_ref().and_then(|libby_book| libby_book.publisher.clone()).unwrap_or_default()}</td>
+            <td class="publication-year">{publication_year(&book, libby_book.as_ref()).map(|year| year.to_string()).unwrap_or_default()}</td>
+            })}
+            // how old the result is, on hover
+            <td class="availability" title={libby_book.as_ref().and_then(|libby_book| libby_book.checked_at).map(|checked_at| checked_ago_label(checked_at, js_sys::Date::now())).unwrap_or_default()}>
+            {match &libby_book {
+            Some(libby_book) => match libby_book.availability {
+            BookAvailability::Available => view! {
+                <a href={libby_book.libby_search_url.clone()} target="_blank">
+                    {available_label(libby_book.available_copies)}
+                </a>
             }.into_view(),
-            Some(libby_book) if libby_book.is_holdable => view! {
-                <a href={libby_book.libby_search_url.clone()} target="_blank">"HOLDABLE"</a>
+            BookAvailability::Holdable => view! {
+                <a href={libby_book.libby_search_url.clone()} target="_blank">
+                    {holdable_label(libby_book.estimated_wait_days)}
+                </a>
             }.into_view(),
-            Some(_) => view! {
+            BookAvailability::Unknown => {
+                let book = book.clone();
+                let mut reason = if libby_book.errored_libraries.is_empty() {
+                    "The lookup failed".to_string()
+                } else {
+                    format!("Couldn't check: {}", libby_book.errored_libraries.join(", "))
+                };
+                // this link's own tooltip hides the cell's
+                if let Some(checked_at) = libby_book.checked_at {
+                    reason.push_str(&format!(" ({})", checked_ago_label(checked_at, js_sys::Date::now())));
+                }
+                view! {
+                <a href="#" title={reason} style="color: #d9534f;" on:click=move |e| {
+                    e.prevent_default();
+                    on_recheck(book.clone());
+                }>"⚠ retry"</a>
+                }.into_view()
+            }
+            BookAvailability::NotOwned => view! {
                 "NOT OWNED"
+                // for when the match missed, e.g. an edition titled differently
+                {(!libby_book.libby_search_url.is_empty()).then(|| view! {
+                    <a
+                        href={libby_book.libby_search_url.clone()}
+                        target="_blank"
+                        style="margin-left: 5px;"
+                        title="Look for the book in Libby yourself, then pin the right title with 📌"
+                    >"search Libby manually"</a>
+                })}
             }.into_view(),
+            },
             None => view! {
                 "..."
             }.into_view(),
             }}
+            {libby_book.as_ref().and_then(non_book_badge)}
+            {newly_available.with(|newly_available| newly_available.get(&book_key).cloned()).map(|libraries| view! {
+                <strong style="margin-left: 5px; color: #2e7d32;" title={format!("Newly available at {}", libraries.join(", "))}>"NEW!"</strong>
+            })}
+            // re-check just this book, e.g. when "not owned" looks like a matching miss
+            {libby_book.is_some().then(|| {
+                let book = book.clone();
+                view! {
+                <button
+                    style="margin-left: 5px;"
+                    title="Check this book again"
+                    on:click=move |_| on_recheck(book.clone())
+                >"↻"</button>
+                }
+            })}
+            // pin the right Overdrive title when matching got it wrong
+            {libby_book.as_ref().map(|libby_book| {
+                let book = book.clone();
+                let pinned_title_id = libby_book.pinned_title_id.clone();
+                let is_pinned = pinned_title_id.is_some();
+                let title = match &pinned_title_id {
+                    Some(title_id) => format!("Pinned to Overdrive title {}. Click to change or unpin", title_id),
+                    None => "Pin this book to the right Libby title".to_string(),
+                };
+                view! {
+                <button
+                    style="margin-left: 5px;"
+                    style:opacity=if is_pinned { "1" } else { "0.5" }
+                    title={title}
+                    aria-pressed={if is_pinned { "true" } else { "false" }}
+                    on:click=move |_| {
+                        let answer = window().prompt_with_message_and_default(
+                            "Paste the book's Libby link or Overdrive title id (e.g. 1234567), or leave empty to unpin:",
+                            pinned_title_id.as_deref().unwrap_or_default(),
+                        );
+                        let Ok(Some(answer)) = answer else {
+                            return;
+                        };
+                        match parse_title_id(&answer) {
+                            Some(title_id) => on_pin((book.clone(), title_id)),
+                            None => {
+                                let _ = window().alert_with_message("That isn't a Libby link or title id.");
+                            }
+                        }
+                    }
+                >"📌"</button>
+                }
+            })}
+            // per-library breakdown toggle, only once there are results to break down
+            {(!library_books.is_empty()).then(|| {
+                let is_expanded = is_expanded.clone();
+                let book_key = book_key.clone();
+                view! {
+                <button
+                    style="margin-left: 5px;"
+                    on:click=move |_| expanded_books.update(|expanded| {
+                        if !expanded.remove(&book_key) {
+                            expanded.insert(book_key.clone());
+                        }
+                    })
+                >{move || if is_expanded() { "▾" } else { "▸" }}</button>
+                }
+            })}
             </td>
+            {formats.get().into_iter().map(|format| view! { <td class="format" data-label={format.label()}>{format_badge(libby_book.as_ref(), format)}</td> }).collect_view()}
+            {show_hoopla.get().then(|| view! { <td class="format" data-label="Hoopla">{hoopla_badge(hoopla.with(|hoopla| hoopla.get(&book_key).cloned()))}</td> })}
         </tr>
-        }
+        <Show when=is_expanded>
+        <tr class="breakdown-row">
+            <td colspan={6 + 2 * usize::from(show_publication.get()) + formats.get().len() + usize::from(show_hoopla.get())}>
+            <table style="margin-left: 20px;">
+            <thead>
+            <tr><th>"Library"</th><th>"Format"</th><th>"Availability"</th><th>"Hold Queue"</th></tr>
+            </thead>
+            <tbody>
+            {library_books.iter().map(|library_book| view! {
+            <tr>
+                <td>
+                    {library_book.library.clone()}
+                    {branches.with(|branches| branches.get(&library_book.library).cloned()).map(|branch| view! { <br /><small style="color: #888;">{format!("Pickup: {}", branch)}</small> })}
+                </td>
+                <td>
+                    {library_book.format.label()}
+                    {audiobook_label(library_book.narrator.as_deref(), library_book.duration.as_deref()).map(|label| view! { <br /><small style="color: #888;">{label}</small> })}
+                </td>
+                <td>
+                <a href={library_book.libby_search_url.clone()} target="_blank">
+                {if library_book.is_available {
+                    available_label(library_book.available_copies)
+                } else if library_book.is_holdable {
+                    holdable_label(library_book.estimated_wait_days)
+                } else {
+                    "NOT OWNED".to_string()
+                }}
+                </a>
+                </td>
+                <td>
+                {match (library_book.holds_count, library_book.owned_copies) {
+                    (Some(holds), Some(copies)) if library_book.is_holdable => format!("{} holds on {} copies", holds, copies),
+                    _ => String::new(),
+                }}
+                </td>
+            </tr>
+            }).collect_view()}
+            </tbody>
+            </table>
+            </td>
+        </tr>
+        </Show>
+        }
         }).collect::<Vec<_>>()
         }}
         </tbody>
@@ -793,19 +1342,210 @@ fn BookTable(
     }
 }
 
+/// Where the list of books to look up comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BookSource {
+    Goodreads,
+    StoryGraph,
+    // a CSV from Goodreads' "Export Library", for private profiles
+    GoodreadsExport,
+    // titles or ISBNs typed in by hand, for a quick check of a few books
+    Pasted,
+    // a friend's public to-read shelf, checked at your libraries, e.g. for gift ideas
+    FriendShelf,
+}
+
 #[component]
 fn HomePage() -> impl IntoView {
     let (books, set_books) = create_signal(Vec::new());
     let is_private_profile = create_rw_signal(false);
+    // whether the selected shelves finished loading, so an empty shelf isn't mistaken for loading
+    let books_loaded = create_rw_signal(false);
+    let book_source = create_rw_signal(BookSource::Goodreads);
+    // the StoryGraph account whose to-read list is used instead of a Goodreads shelf
+    let storygraph_username = create_rw_signal(String::new());
+    let storygraph_failed = create_rw_signal(false);
+    // the friend whose to-read shelf is browsed, kept apart from your own user id so it isn't
+    // remembered as yours, and why it couldn't be read
+    let friend_input = create_rw_signal(String::new());
+    let friend_user_id = create_rw_signal(None::<String>);
+    let friend_failed = create_rw_signal(None::<String>);
+    let export_failed = create_rw_signal(false);
+    // the address availability emails go to, and how signing up went
+    let notify_email = create_rw_signal(String::new());
+    let notify_status = create_rw_signal(None::<Result<(), String>>);
+    let pasted_books = create_rw_signal(String::new());
+    // available books first, the same direction a click on the availability header starts with
     let (sort_by, set_sort_by) = create_signal(String::from("availability"));
     let (sort_order, set_sort_order) = create_signal(String::from("asc"));
+    create_effect(move |_| {
+        if let Some((column, order)) = storage::load(storage::SORT_KEY)
+            .as_deref()
+            .and_then(|saved| saved.split_once(':'))
+            .filter(|(column, order)| {
+                SORT_COLUMNS.contains(column) && matches!(*order, "asc" | "desc")
+            })
+        {
+            set_sort_by(column.to_string());
+            set_sort_order(order.to_string());
+        }
+    });
+    create_effect(move |_| {
+        storage::save(
+            storage::SORT_KEY,
+            &format!("{}:{}", sort_by.get(), sort_order.get()),
+        );
+    });
     let user_id = create_rw_signal(String::new());
     let shelves = create_rw_signal(Vec::<String>::new());
-    let selected_shelf = create_rw_signal(String::new());
+    let selected_shelves = create_rw_signal(Vec::<String>::new());
+    // set by the shelf toggles, so the new shelf is searched as soon as it loads
+    let search_when_loaded = create_rw_signal(false);
+    // the shelf named in a pasted Goodreads link, selected once the user's shelves load
+    let requested_shelf = create_rw_signal(None::<String>);
+    // what's in the user id box, which only becomes `user_id` once it parses as one
+    let user_id_input = create_rw_signal(String::new());
+    let pending_user_id = store_value(None::<leptos::leptos_dom::helpers::TimeoutHandle>);
+    let invalid_user_id = move || {
+        let input = user_id_input.get();
+        !input.trim().is_empty() && goodreads::parse_user_input(&input).is_none()
+    };
+    // ids restored from the url or local storage show up in the box too
+    create_effect(move |_| user_id_input.set(user_id.get()));
+    // a second Goodreads user (e.g. a partner) whose same shelves are combined with these, to
+    // find shared reads
+    let partner_user_id = create_rw_signal(None::<String>);
+    let partner_input = create_rw_signal(String::new());
+    let shelf_combination = create_rw_signal(goodreads::ShelfCombination::Both);
+    let partner_failed = create_rw_signal(false);
     let (search_libraries, set_search_libraries) = create_signal(Vec::<SearchLibrary>::new());
 
+    let selected_formats = create_rw_signal(BookFormat::DEFAULT.to_vec());
+    // Overdrive language codes to search in, none meaning any language
+    let selected_languages = create_rw_signal(Vec::<String>::new());
+    create_effect(move |_| {
+        if let Some(saved) = storage::load(storage::LANGUAGES_KEY) {
+            selected_languages.set(
+                saved
+                    .split(',')
+                    .filter(|language| !language.is_empty())
+                    .map(|language| language.to_string())
+                    .collect(),
+            );
+        }
+    });
+    create_effect(move |_| {
+        storage::save(storage::LANGUAGES_KEY, &selected_languages.get().join(","));
+    });
+    let match_strictness = create_rw_signal(MatchStrictness::default());
+    // on unless this browser turned it off
+    let exclude_preorders = create_rw_signal(true);
+    create_effect(move |_| {
+        if storage::load(storage::EXCLUDE_PREORDERS_KEY).as_deref() == Some("false") {
+            exclude_preorders.set(false);
+        }
+    });
+    create_effect(move |_| {
+        storage::save(
+            storage::EXCLUDE_PREORDERS_KEY,
+            &exclude_preorders.get().to_string(),
+        );
+    });
+    let check_concurrency = create_rw_signal(DEFAULT_CHECK_CONCURRENCY);
+    // restore the saved limit, then save every change. local storage only exists in the browser
+    create_effect(move |_| {
+        if let Some(saved) = storage::load(storage::CHECK_CONCURRENCY_KEY)
+            .and_then(|saved| saved.parse::<usize>().ok())
+            .filter(|saved| CHECK_CONCURRENCY_RANGE.contains(saved))
+        {
+            check_concurrency.set(saved);
+        }
+    });
+    create_effect(move |_| {
+        storage::save(
+            storage::CHECK_CONCURRENCY_KEY,
+            &check_concurrency.get().to_string(),
+        );
+    });
+    let availability_filter = create_rw_signal(
+        BookAvailability::ALL
+            .into_iter()
+            .map(Some)
+            .chain([None])
+            .collect::<Vec<_>>(),
+    );
+    let search_text = create_rw_signal(String::new());
+    let group_by_series = create_rw_signal(false);
+    let compact_table = create_rw_signal(false);
+    create_effect(move |_| {
+        if storage::load(storage::DENSITY_KEY).as_deref() == Some("compact") {
+            compact_table.set(true);
+        }
+    });
+    create_effect(move |_| {
+        let density = if compact_table.get() {
+            "compact"
+        } else {
+            "comfortable"
+        };
+        storage::save(storage::DENSITY_KEY, density);
+    });
+    let show_publication = create_rw_signal(false);
+    create_effect(move |_| {
+        if storage::load(storage::PUBLICATION_COLUMNS_KEY).as_deref() == Some("true") {
+            show_publication.set(true);
+        }
+    });
+    create_effect(move |_| {
+        storage::save(
+            storage::PUBLICATION_COLUMNS_KEY,
+            &show_publication.get().to_string(),
+        );
+    });
     let selected_library_website_ids = create_rw_signal(Vec::<String>::new());
     let selected_libraries = create_rw_signal(Vec::<Library>::new());
+    let selected_branches = create_rw_signal(HashMap::<String, String>::new());
+    // restore the pickup branches picked last time, then save every change
+    create_effect(move |_| {
+        if let Some(saved) = storage::load(storage::BRANCHES_KEY)
+            .and_then(|saved| serde_json::from_str::<HashMap<String, String>>(&saved).ok())
+        {
+            selected_branches.set(saved);
+        }
+    });
+    create_effect(move |_| {
+        if let Ok(branches) = serde_json::to_string(&selected_branches.get()) {
+            storage::save(storage::BRANCHES_KEY, &branches);
+        }
+    });
+    let hoopla_libraries = create_rw_signal(Vec::<String>::new());
+    // restore which libraries offer Hoopla, then save every change
+    create_effect(move |_| {
+        if let Some(saved) = storage::load(storage::HOOPLA_LIBRARIES_KEY) {
+            hoopla_libraries.set(
+                saved
+                    .split(',')
+                    .filter(|website_id| !website_id.is_empty())
+                    .map(|website_id| website_id.to_string())
+                    .collect(),
+            );
+        }
+    });
+    create_effect(move |_| {
+        storage::save(
+            storage::HOOPLA_LIBRARIES_KEY,
+            &hoopla_libraries.get().join(","),
+        );
+    });
+    // Hoopla is only searched when one of the selected libraries offers it
+    let hoopla_enabled = Signal::derive(move || {
+        let hoopla_libraries = hoopla_libraries.get();
+        selected_library_website_ids
+            .get()
+            .iter()
+            .any(|website_id| hoopla_libraries.contains(website_id))
+    });
+    let hoopla_results = create_rw_signal(HooplaResults::new());
     // selected_libraries is derived from selected_library_website_ids
     create_effect(move |_| {
         let selected_library_website_ids_clone = selected_library_website_ids.get().clone();
@@ -837,7 +1577,7 @@ fn HomePage() -> impl IntoView {
 
         let futures: Vec<_> = new_libs_to_fetch
             .into_iter()
-            .map(|website_id| get_library_from_website_id(website_id))
+            .map(get_library_from_website_id)
             .collect();
 
         // Fetch libraries asynchronously and update the signal as they arrive
@@ -859,40 +1599,205 @@ fn HomePage() -> impl IntoView {
         });
     });
     let (libby_progress, set_libby_progress) = create_signal(0);
+    // when the running search began, in ms since the epoch, for the progress estimate
+    let search_started_at = create_rw_signal(None::<f64>);
     let (available_count, set_available_count) = create_signal(0);
     let (holdable_count, set_holdable_count) = create_signal(0);
     let (not_owned_count, set_not_owned_count) = create_signal(0);
+    let (unknown_count, set_unknown_count) = create_signal(0);
+    // what screen readers are told about a running search: the latest totals, at most every
+    // PROGRESS_ANNOUNCE_INTERVAL_MS so they don't read out every single book, and the end
+    let progress_announcement = create_rw_signal(String::new());
+    let last_announced_at = store_value(0.0);
+    create_effect(move |_| {
+        let done = libby_progress.get().max(0) as usize;
+        let total = books.with(Vec::len);
+        if done == 0 || total == 0 {
+            return;
+        }
+        let now = js_sys::Date::now();
+        if done < total && now - last_announced_at.get_value() < PROGRESS_ANNOUNCE_INTERVAL_MS {
+            return;
+        }
+        last_announced_at.set_value(now);
+        // the counts are bumped before the progress, so they're already up to date here
+        progress_announcement.set(format!(
+            "Checked {} of {} books. Available: {}, Holdable: {}, Not Owned: {}, Unknown: {}",
+            done,
+            total,
+            available_count.get_untracked(),
+            holdable_count.get_untracked(),
+            not_owned_count.get_untracked(),
+            unknown_count.get_untracked(),
+        ));
+    });
     let (availability, set_availability) = create_signal(Vec::new());
+    // when the results on screen were checked, in ms since the epoch, if they're all in
+    let last_checked = create_rw_signal(None::<f64>);
+    // (title, author) of books that became available since this user's last search, with where
+    let newly_available = create_rw_signal(HashMap::<(String, String), Vec<String>>::new());
+    // what saved results must match to be restored. library order doesn't change the results
+    let availability_key = move || {
+        let mut website_ids = selected_library_website_ids.get_untracked();
+        website_ids.sort();
+        format!(
+            "{}|{}|{}|{:?}|{}|{}|{}|{}|{}",
+            user_id.get_untracked(),
+            selected_shelves.get_untracked().join(","),
+            partner_user_id.get_untracked().unwrap_or_default(),
+            shelf_combination.get_untracked(),
+            website_ids.join(","),
+            selected_formats
+                .get_untracked()
+                .iter()
+                .map(|format| format.overdrive_type_id())
+                .collect::<Vec<_>>()
+                .join(","),
+            selected_languages.get_untracked().join(","),
+            exclude_preorders.get_untracked(),
+            match_strictness.get_untracked().param(),
+        )
+    };
 
     let fetch_books = move || {
+        if book_source.get_untracked() == BookSource::GoodreadsExport {
+            // the uploaded file is the whole list, there is nothing to re-fetch
+            return;
+        }
+        if book_source.get_untracked() == BookSource::Pasted {
+            set_books.set(goodreads::parse_pasted_books(&pasted_books.get_untracked()));
+            books_loaded.set(true);
+            return;
+        }
+        if book_source.get_untracked() == BookSource::FriendShelf {
+            let Some(friend) = goodreads::parse_user_input(&friend_input.get_untracked())
+                .map(|parsed| parsed.user_id)
+            else {
+                friend_failed.set(Some(
+                    "That doesn't look like a Goodreads user ID or profile link".to_string(),
+                ));
+                return;
+            };
+            friend_user_id.set(Some(friend.clone()));
+            books_loaded.set(false);
+            friend_failed.set(None);
+            spawn_local(async move {
+                match get_goodreads_books(friend, vec![DEFAULT_SHELF.to_string()]).await {
+                    Ok(fetched_books) => {
+                        set_books.set(fetched_books);
+                        books_loaded.set(true);
+                    }
+                    Err(err) => {
+                        logging::error!("Error fetching the friend's books. {}", err);
+                        friend_failed.set(Some(match err {
+                            ServerFnError::WrappedServerError(GoodreadsError::PrivateProfile) => {
+                                "Their Goodreads profile is private".to_string()
+                            }
+                            ServerFnError::WrappedServerError(GoodreadsError::NotFound) => {
+                                "There's no Goodreads user with that ID".to_string()
+                            }
+                            _ => "Couldn't load their to-read shelf, try again".to_string(),
+                        }));
+                    }
+                }
+            });
+            return;
+        }
+        if book_source.get_untracked() == BookSource::StoryGraph {
+            let username = storygraph_username.get_untracked();
+            books_loaded.set(false);
+            storygraph_failed.set(false);
+            spawn_local(async move {
+                match get_storygraph_books(username).await {
+                    Ok(fetched_books) => {
+                        set_books.set(fetched_books);
+                        books_loaded.set(true);
+                    }
+                    Err(err) => {
+                        logging::error!("Error fetching StoryGraph books. {}", err);
+                        storygraph_failed.set(true);
+                    }
+                }
+            });
+            return;
+        }
         let user_id = user_id.get();
-        let selected_shelf = selected_shelf.get();
+        let selected_shelves = selected_shelves.get();
+        let partner = partner_user_id
+            .get_untracked()
+            .filter(|partner| *partner != user_id);
+        let combination = shelf_combination.get_untracked();
+        books_loaded.set(false);
+        partner_failed.set(false);
         spawn_local(async move {
-            match get_goodreads_books(user_id, selected_shelf).await {
-                Ok(fetched_books) => set_books.set(fetched_books),
+            // both people's shelves at once
+            let partner_fetch = async {
+                match partner {
+                    Some(partner) => {
+                        Some(get_goodreads_books(partner, selected_shelves.clone()).await)
+                    }
+                    None => None,
+                }
+            };
+            let (result, partner_result) = futures::join!(
+                get_goodreads_books(user_id, selected_shelves.clone()),
+                partner_fetch
+            );
+            match result {
+                Ok(fetched_books) => {
+                    let fetched_books = match partner_result {
+                        Some(Ok(partner_books)) => {
+                            goodreads::combine_shelves(fetched_books, partner_books, combination)
+                        }
+                        Some(Err(err)) => {
+                            logging::error!("Error fetching the second user's books. {}", err);
+                            partner_failed.set(true);
+                            fetched_books
+                        }
+                        None => fetched_books,
+                    };
+                    set_books.set(fetched_books);
+                    books_loaded.set(true);
+                }
                 Err(e) => {
-                    is_private_profile.update(|is_private| {
-                        // TODO: this is a hacky way to check if the profile is private
-                        // instead, figure out how to return a custom error from the server fn
-                        // and check for that here
-                        *is_private = e.to_string().contains("Private profile");
-                    });
+                    let private = matches!(
+                        e,
+                        ServerFnError::WrappedServerError(GoodreadsError::PrivateProfile)
+                    );
+                    if private {
+                        // don't restore a profile we can't read on the next visit
+                        storage::remove(storage::USER_ID_KEY);
+                    }
+                    is_private_profile.set(private);
                 }
             }
         });
     };
 
-    create_effect(move |_| {
-        let shelf = selected_shelf.get();
-        // when a new shelf is selected, remove all existing progress and availability data
+    // removes the books and all progress and availability data, before loading another list
+    let clear_books = move || {
         set_libby_progress.update(|progress| *progress = 0);
         set_available_count.update(|available| *available = 0);
         set_holdable_count.update(|holdable| *holdable = 0);
         set_not_owned_count.update(|not_owned| *not_owned = 0);
+        set_unknown_count.update(|unknown| *unknown = 0);
         set_availability.update(|availability| availability.clear());
         set_books.update(|books| books.clear());
+        books_loaded.set(false);
+        last_checked.set(None);
+        hoopla_results.update(|hoopla| hoopla.clear());
+        newly_available.update(|newly_available| newly_available.clear());
+    };
+
+    create_effect(move |_| {
+        let shelves = selected_shelves.get();
+        // a different second user, or way of combining, makes a different list too
+        partner_user_id.track();
+        shelf_combination.track();
+        // when the shelf selection changes, remove all existing progress and availability data
+        clear_books();
         // create_effects are called once on component mount
-        if !shelf.is_empty() {
+        if !shelves.is_empty() && book_source.get_untracked() == BookSource::Goodreads {
             fetch_books();
         }
     });
@@ -902,14 +1807,27 @@ fn HomePage() -> impl IntoView {
             match get_goodreads_shelves(user_id.get()).await {
                 Ok(found_shelves) => {
                     logging::log!("Found shelves: {:?}", found_shelves);
+                    storage::save(storage::USER_ID_KEY, &user_id.get_untracked());
                     shelves.update(|shelves| {
                         *shelves = found_shelves.clone();
                     });
-                    // force select to-read shelf
-                    selected_shelf.update(|shelf| *shelf = "to-read".to_string());
+                    // select the shelf from a pasted link if the user has it, else to-read
+                    let shelf = requested_shelf
+                        .get_untracked()
+                        .filter(|shelf| found_shelves.contains(shelf))
+                        .unwrap_or_else(|| DEFAULT_SHELF.to_string());
+                    selected_shelves.set(vec![shelf]);
                 }
                 Err(err) => {
                     logging::error!("Error fetching shelves. {}", err);
+                    if let ServerFnError::WrappedServerError(
+                        error @ (GoodreadsError::NotFound | GoodreadsError::PrivateProfile),
+                    ) = err
+                    {
+                        // a stale or deleted id, start from an empty form next time
+                        storage::remove(storage::USER_ID_KEY);
+                        is_private_profile.set(error == GoodreadsError::PrivateProfile);
+                    }
                 }
             }
         });
@@ -938,6 +1856,16 @@ fn HomePage() -> impl IntoView {
         logging::log!("User id was set from url.");
         user_id.update(|new_id| *new_id = user_id_from_url_value);
         fetch_shelves();
+    } else {
+        // restoring the last user id kicks off fetch_shelves, which then loads the to-read shelf
+        create_effect(move |_| {
+            if let Some(saved) = storage::load(storage::USER_ID_KEY) {
+                if !saved.is_empty() {
+                    logging::log!("User id was restored from local storage.");
+                    user_id.set(saved);
+                }
+            }
+        });
     };
 
     // get a list of website ids from the url query param, if it exists
@@ -964,132 +1892,719 @@ fn HomePage() -> impl IntoView {
     let selected_library_website_ids_from_url_value = selected_library_website_ids_from_url();
     if !selected_library_website_ids_from_url_value.is_empty() {
         selected_library_website_ids.set(selected_library_website_ids_from_url_value.clone());
+    } else {
+        // nothing in the url, so fall back to the libraries this browser picked last time.
+        // this has to run in an effect because local storage only exists in the browser
+        create_effect(move |_| {
+            if let Some(saved) = storage::load(storage::LIBRARIES_KEY) {
+                let saved = saved
+                    .split(',')
+                    .filter(|website_id| !website_id.is_empty())
+                    .map(|website_id| website_id.to_string())
+                    .collect::<Vec<String>>();
+                if !saved.is_empty() {
+                    selected_library_website_ids.set(saved);
+                }
+            }
+        });
+    }
+    // remember the selection for the next visit. created after the restore above so it
+    // never overwrites the saved libraries with the empty initial selection
+    create_effect(move |_| {
+        storage::save(
+            storage::LIBRARIES_KEY,
+            &selected_library_website_ids.get().join(","),
+        );
+    });
+    // formats is a string like "ebook,audiobook"
+    let selected_formats_from_url = query.with_untracked(|params| {
+        params
+            .as_ref()
+            .ok()
+            .and_then(|params| params.formats.clone())
+            .map(|formats| {
+                formats
+                    .split(',')
+                    .filter_map(BookFormat::from_overdrive_type_id)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    });
+    if !selected_formats_from_url.is_empty() {
+        selected_formats.set(selected_formats_from_url);
+    }
+    let match_strictness_from_url = query.with_untracked(|params| {
+        params
+            .as_ref()
+            .ok()
+            .and_then(|params| params.matching.as_deref())
+            .and_then(MatchStrictness::from_param)
+    });
+    if let Some(strictness) = match_strictness_from_url {
+        match_strictness.set(strictness);
     }
+
+    // keep the address bar shareable: it always reflects the current user, libraries, formats
+    // and match strictness
+    create_effect(move |_| {
+        let share_query = format!(
+            "?user_id={}&libraries={}&formats={}&matching={}",
+            user_id.get(),
+            selected_library_website_ids.get().join(","),
+            selected_formats
+                .get()
+                .iter()
+                .map(|format| format.overdrive_type_id())
+                .collect::<Vec<_>>()
+                .join(","),
+            match_strictness.get().param(),
+        );
+        if let Ok(history) = window().history() {
+            let _ = history.replace_state_with_url(&JsValue::NULL, "", Some(&share_query));
+        }
+    });
+
     logging::log!("User ID {:?}", user_id.get());
     logging::log!(
         "Selected libraries website IDs: {:?}",
         selected_library_website_ids.get()
     );
 
-    let fetch_availability = move || {
-        set_libby_progress.update(|progress| *progress = 0);
-        set_available_count.update(|available| *available = 0);
-        set_holdable_count.update(|holdable| *holdable = 0);
-        set_not_owned_count.update(|not_owned| *not_owned = 0);
-        set_availability.update(|availability| availability.clear());
+    // the summary count a result is tallied in
+    let count_for = move |availability: BookAvailability| match availability {
+        BookAvailability::Available => set_available_count,
+        BookAvailability::Holdable => set_holdable_count,
+        BookAvailability::NotOwned => set_not_owned_count,
+        BookAvailability::Unknown => set_unknown_count,
+    };
+
+    // the id of the search check_book's results are tallied under on the server, new whenever
+    // checks start from idle
+    let search_id = store_value(String::new());
+
+    // checks a single book and records the result, bumping the matching summary count.
+    // `bypass_cache` asks Overdrive again rather than taking a recently cached answer
+    let check_book = move |book: GoodreadsBook,
+                           bypass_cache: bool|
+          -> Pin<Box<dyn Future<Output = ()> + 'static>> {
+        // Wrap the async block in a Box to erase its type
+        Box::pin(async move {
+            let pinned_title_id = load_pins()
+                .get(&user_id.get_untracked())
+                .and_then(|pins| pins.get(&pin_key(&book)))
+                .cloned();
+            let fetched_availability = match get_libby_availability(
+                book.clone(),
+                selected_libraries(),
+                selected_formats.get_untracked(),
+                selected_languages.get_untracked(),
+                exclude_preorders.get_untracked(),
+                match_strictness.get_untracked(),
+                pinned_title_id,
+                bypass_cache,
+                Some(search_id.get_value()),
+            )
+            .await
+            {
+                Ok(fetched_availability) => fetched_availability,
+                Err(err) => {
+                    logging::error!("Error checking {}. {}", book.title, err);
+                    // the clock is read through js, chrono can't ask for "now" in the browser
+                    LibbyBook {
+                        checked_at: DateTime::from_timestamp_millis(js_sys::Date::now() as i64),
+                        ..LibbyBook::unknown(&book)
+                    }
+                }
+            };
+            count_for(fetched_availability.availability).update(|count| *count += 1);
+            set_availability.update(|availability| {
+                availability.push(fetched_availability);
+            });
+            set_libby_progress.update(|progress| *progress += 1);
+            // after Libby, so the main results (and the progress bar) don't wait on it
+            if hoopla_enabled.get_untracked() {
+                let result = get_hoopla_availability(book.clone()).await.map_err(|err| {
+                    logging::error!("Error searching Hoopla for {}. {}", book.title, err);
+                    err.to_string()
+                });
+                hoopla_results.update(|hoopla| {
+                    hoopla.insert((book.title, book.author), result);
+                });
+            }
+        })
+    };
+
+    // save a finished Goodreads search for the next visit
+    let save_availability = move || {
+        if book_source.get_untracked() != BookSource::Goodreads {
+            return;
+        }
+        let saved = SavedAvailability {
+            key: availability_key(),
+            checked_at: js_sys::Date::now(),
+            availability: availability.get_untracked(),
+        };
+        if let Ok(json) = serde_json::to_string(&saved) {
+            storage::save(storage::AVAILABILITY_KEY, &json);
+        }
+        last_checked.set(Some(saved.checked_at));
+
+        // compare with this user's last search in this browser
+        let mut known = storage::load(storage::BOOK_STATUSES_KEY)
+            .and_then(|json| serde_json::from_str::<KnownStatuses>(&json).ok())
+            .unwrap_or_default();
+        let previous = known.entry(user_id.get_untracked()).or_default();
+        newly_available.update(|newly_available| {
+            newly_available.clear();
+            for book_status in saved
+                .availability
+                .iter()
+                .flat_map(|libby_book| libby_book.library_statuses())
+            {
+                let key = format!(
+                    "{}|{}|{}",
+                    book_status.title, book_status.author, book_status.library
+                );
+                let before = previous.insert(key, book_status.status);
+                if book_status.status.became_available(before) {
+                    newly_available
+                        .entry((book_status.title, book_status.author))
+                        .or_default()
+                        .push(book_status.library);
+                }
+            }
+        });
+        if let Ok(json) = serde_json::to_string(&known) {
+            storage::save(storage::BOOK_STATUSES_KEY, &json);
+        }
+    };
 
-        let books = books.get().clone();
+    // how many check_books runs are still going, and how to abort them from the Cancel button
+    let (running_checks, set_running_checks) = create_signal(0usize);
+    let check_abort_handles = store_value(Vec::<AbortHandle>::new());
 
-        let fetch_concurrent = async move {
+    // checks the given books, keeping <concurrency_limit> requests in flight at all times.
+    // re-checks pass `bypass_cache`, or they could get back the very answer being re-checked
+    let check_books = move |books_to_check: Vec<GoodreadsBook>, bypass_cache: bool| {
+        if running_checks.get_untracked() == 0 {
+            search_id.set_value(new_search_id());
+        }
+        let (fetch_concurrent, abort_handle) = abortable(async move {
             let mut in_flight = FuturesUnordered::new();
-            let mut book_iter = books.into_iter();
-            let concurrency_limit = 5;
+            let mut book_iter = books_to_check.into_iter();
+            let concurrency_limit = check_concurrency.get_untracked();
 
             // Start initial batch of requests (up to concurrency limit)
             for _ in 0..concurrency_limit {
                 if let Some(book) = book_iter.next() {
-                    let book_clone = book.clone();
-
-                    // Wrap the async block in a Box to erase its type
-                    let handle: Pin<Box<dyn Future<Output = ()> + 'static>> =
-                        Box::pin(async move {
-                            match get_libby_availability(book_clone, selected_libraries()).await {
-                                Ok(fetched_availability) => {
-                                    let availability_clone = fetched_availability.clone();
-                                    set_availability.update(|availability| {
-                                        availability.push(availability_clone);
-                                    });
-                                    if fetched_availability.is_available {
-                                        set_available_count.update(|available| *available += 1);
-                                    } else if fetched_availability.is_holdable {
-                                        set_holdable_count.update(|holdable| *holdable += 1);
-                                    } else {
-                                        set_not_owned_count.update(|not_owned| *not_owned += 1);
-                                    }
-                                }
-                                Err(_) => {
-                                    // Handle error
-                                }
-                            }
-                            set_libby_progress.update(|progress| *progress += 1);
-                        });
-
-                    in_flight.push(handle);
+                    in_flight.push(check_book(book, bypass_cache));
                 }
             }
 
             // Process the queue dynamically, keeping <concurrency_limit> requests in flight at all times
-            while let Some(_) = in_flight.next().await {
+            while in_flight.next().await.is_some() {
                 // When a request finishes, start another if there are more books to process
                 if let Some(book) = book_iter.next() {
-                    let book_clone = book.clone();
-
-                    // Wrap the async block in a Box to erase its type
-                    let handle: Pin<Box<dyn Future<Output = ()> + 'static>> =
-                        Box::pin(async move {
-                            match get_libby_availability(book_clone, selected_libraries()).await {
-                                Ok(fetched_availability) => {
-                                    let availability_clone = fetched_availability.clone();
-                                    set_availability.update(|availability| {
-                                        availability.push(availability_clone);
-                                    });
-                                    if fetched_availability.is_available {
-                                        set_available_count.update(|available| *available += 1);
-                                    } else if fetched_availability.is_holdable {
-                                        set_holdable_count.update(|holdable| *holdable += 1);
-                                    } else {
-                                        set_not_owned_count.update(|not_owned| *not_owned += 1);
-                                    }
-                                }
-                                Err(_) => {
-                                    // Handle error
-                                }
-                            }
-                            set_libby_progress.update(|progress| *progress += 1);
-                        });
+                    in_flight.push(check_book(book, bypass_cache));
+                }
+            }
+        });
+        check_abort_handles.update_value(|handles| handles.push(abort_handle));
+        set_running_checks.update(|running| *running += 1);
 
-                    in_flight.push(handle);
+        // Trigger the async function that controls concurrency. Aborting it drops the requests
+        // still in flight, while the results already recorded stay put
+        spawn_local(async move {
+            let finished = fetch_concurrent.await.is_ok();
+            set_running_checks.update(|running| *running -= 1);
+            // nothing left to estimate, and a later re-check shouldn't extrapolate from this run
+            if running_checks.get_untracked() == 0 {
+                search_started_at.set(None);
+                // a cancelled search would skew the match rate toward whatever ran first, so
+                // its tally is left to expire
+                if finished {
+                    save_availability();
+                    if let Err(err) = finish_search(search_id.get_value()).await {
+                        logging::error!("Error finishing the search. {}", err);
+                    }
                 }
             }
+        });
+    };
+
+    // once the shelf loads, bring back the results of the same search if they're fresh enough
+    create_effect(move |_| {
+        if !books_loaded.get()
+            || book_source.get_untracked() != BookSource::Goodreads
+            || !availability.get_untracked().is_empty()
+            || running_checks.get_untracked() != 0
+        {
+            return;
+        }
+        let Some(saved) = storage::load(storage::AVAILABILITY_KEY)
+            .and_then(|saved| serde_json::from_str::<SavedAvailability>(&saved).ok())
+            .filter(|saved| saved.key == availability_key())
+            .filter(|saved| js_sys::Date::now() - saved.checked_at < SAVED_AVAILABILITY_TTL_MS)
+        else {
+            return;
         };
+        // books removed from the shelf since don't come back
+        let shelf = books.get_untracked();
+        let restored = saved
+            .availability
+            .into_iter()
+            .filter(|libby_book| shelf.iter().any(|book| libby_book.is_for(book)))
+            .collect::<Vec<_>>();
+        for libby_book in &restored {
+            count_for(libby_book.availability).update(|count| *count += 1);
+        }
+        set_libby_progress.set(restored.len() as i32);
+        set_availability.set(restored);
+        last_checked.set(Some(saved.checked_at));
+    });
 
-        // Trigger the async function that controls concurrency
-        spawn_local(fetch_concurrent);
+    let cancel_checks = move || {
+        check_abort_handles.update_value(|handles| {
+            for handle in handles.drain(..) {
+                handle.abort();
+            }
+        });
+    };
+
+    let fetch_availability = move || {
+        search_started_at.set(Some(js_sys::Date::now()));
+        last_checked.set(None);
+        hoopla_results.update(|hoopla| hoopla.clear());
+        newly_available.update(|newly_available| newly_available.clear());
+        set_libby_progress.update(|progress| *progress = 0);
+        set_available_count.update(|available| *available = 0);
+        set_holdable_count.update(|holdable| *holdable = 0);
+        set_not_owned_count.update(|not_owned| *not_owned = 0);
+        set_unknown_count.update(|unknown| *unknown = 0);
+        set_availability.update(|availability| availability.clear());
+
+        check_books(books.get(), false);
+    };
+
+    // the search a shelf toggle asked for, once that shelf's books are in
+    create_effect(move |_| {
+        if books_loaded.get() && search_when_loaded.get_untracked() {
+            search_when_loaded.set(false);
+            if !selected_libraries.get_untracked().is_empty() {
+                fetch_availability();
+            }
+        }
+    });
+
+    // drops a book's result and takes it back out of the summary counts and progress
+    let forget_result = move |book: &GoodreadsBook| {
+        let mut forgotten = None;
+        set_availability.update(|availability| {
+            if let Some(index) = availability
+                .iter()
+                .position(|libby_book| libby_book.is_for(book))
+            {
+                forgotten = Some(availability.remove(index).availability);
+            }
+        });
+        hoopla_results.update(|hoopla| {
+            hoopla.remove(&(book.title.clone(), book.author.clone()));
+        });
+        if let Some(forgotten) = forgotten {
+            count_for(forgotten).update(|count| *count -= 1);
+            set_libby_progress.update(|progress| *progress -= 1);
+        }
+    };
+
+    // checks a single book again, replacing its previous result
+    let recheck_book = Callback::new(move |book: GoodreadsBook| {
+        forget_result(&book);
+        check_books(vec![book], true);
+    });
+
+    // pins (or with an empty id unpins) a book's match in this browser, then checks it again
+    // to use the pin
+    let pin_book = Callback::new(move |(book, title_id): (GoodreadsBook, String)| {
+        let mut pins = load_pins();
+        let user_pins = pins.entry(user_id.get_untracked()).or_default();
+        if title_id.is_empty() {
+            user_pins.remove(&pin_key(&book));
+        } else {
+            user_pins.insert(pin_key(&book), title_id);
+        }
+        if let Ok(json) = serde_json::to_string(&pins) {
+            storage::save(storage::PINS_KEY, &json);
+        }
+        recheck_book(book);
+    });
+
+    // books on the shelf that have no result from the last search, e.g. ones added since then
+    let unchecked_books = move || {
+        let availability = availability.get();
+        books
+            .get()
+            .into_iter()
+            .filter(|book| {
+                !availability
+                    .iter()
+                    .any(|libby_book| libby_book.is_for(book))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // books whose last result was not owned or unknown, often just a transient failure
+    let unsuccessful_books = move || {
+        let availability = availability.get();
+        books
+            .get()
+            .into_iter()
+            .filter(|book| {
+                availability.iter().any(|libby_book| {
+                    libby_book.is_for(book)
+                        && matches!(
+                            libby_book.availability,
+                            BookAvailability::NotOwned | BookAvailability::Unknown
+                        )
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // checks the not owned and unknown books again, the counts update as each result arrives
+    let recheck_unsuccessful = move || {
+        let books_to_check = unsuccessful_books();
+        for book in &books_to_check {
+            forget_result(book);
+        }
+        check_books(books_to_check, true);
+    };
+
+    // only checks the new books, merging their results into the existing ones
+    let fetch_new_availability = move || {
+        check_books(unchecked_books(), false);
     };
 
     view! {
             <h1>"LibbyReads"</h1>
             <p>"Search Libby for your Goodreads books" </p>
             <div style="display: flex; align-items: center; gap: 10px; flex-wrap: wrap;">
+                <select
+                    title="Where your reading list comes from"
+                    on:change=move |e| {
+                        let source = match event_target_value(&e).as_str() {
+                            "storygraph" => BookSource::StoryGraph,
+                            "goodreads-export" => BookSource::GoodreadsExport,
+                            "pasted" => BookSource::Pasted,
+                            "friend" => BookSource::FriendShelf,
+                            _ => BookSource::Goodreads,
+                        };
+                        book_source.set(source);
+                        clear_books();
+                        if source == BookSource::Goodreads && !selected_shelves.get_untracked().is_empty() {
+                            fetch_books();
+                        }
+                    }
+                >
+                    <option value="goodreads" selected=move || book_source.get() == BookSource::Goodreads>"Goodreads"</option>
+                    <option value="storygraph" selected=move || book_source.get() == BookSource::StoryGraph>"StoryGraph"</option>
+                    <option value="goodreads-export" selected=move || book_source.get() == BookSource::GoodreadsExport>"Goodreads export (CSV)"</option>
+                    <option value="pasted" selected=move || book_source.get() == BookSource::Pasted>"Titles or ISBNs"</option>
+                    <option value="friend" selected=move || book_source.get() == BookSource::FriendShelf>"A friend's Goodreads shelf"</option>
+                </select>
+                <Show
+                    when=move || book_source.get() == BookSource::Goodreads
+                    fallback=move || match book_source.get() {
+                        BookSource::GoodreadsExport => view! {
+                        <input
+                            type="file"
+                            accept=".csv,text/csv"
+                            title="The CSV from \"Export Library\" in your Goodreads settings"
+                            on:change=move |e| {
+                                let Some(file) = event_target::<web_sys::HtmlInputElement>(&e)
+                                    .files()
+                                    .and_then(|files| files.get(0))
+                                else {
+                                    return;
+                                };
+                                clear_books();
+                                export_failed.set(false);
+                                spawn_local(async move {
+                                    let text = wasm_bindgen_futures::JsFuture::from(file.text())
+                                        .await
+                                        .ok()
+                                        .and_then(|text| text.as_string());
+                                    match text.and_then(|text| goodreads::parse_export_csv(&text, DEFAULT_SHELF)) {
+                                        Some(exported_books) => {
+                                            set_books.set(exported_books);
+                                            books_loaded.set(true);
+                                        }
+                                        None => export_failed.set(true),
+                                    }
+                                });
+                            }
+                        />
+                        <Show when=move || export_failed.get()>
+                            <span style="color: #d9534f;">"That file doesn't look like a Goodreads library export"</span>
+                        </Show>
+                    }.into_view(),
+                        BookSource::Pasted => view! {
+                            <textarea
+                                rows="4"
+                                cols="40"
+                                placeholder="One title or ISBN per line"
+                                prop:value=move || pasted_books.get()
+                                on:input=move |e| pasted_books.set(event_target_value(&e))
+                            ></textarea>
+                            <button
+                                disabled=move || pasted_books.get().trim().is_empty()
+                                on:click=move |_| {
+                                    clear_books();
+                                    fetch_books();
+                                    if !selected_libraries.get_untracked().is_empty() {
+                                        fetch_availability();
+                                    }
+                                }
+                            >"Check these books"</button>
+                        }.into_view(),
+                        BookSource::FriendShelf => view! {
+                        <form
+                            style="display: flex; gap: 10px;"
+                            on:submit=move |e| {
+                                e.prevent_default();
+                                clear_books();
+                                fetch_books();
+                            }
+                        >
+                            <input
+                                type="text"
+                                placeholder="Friend's Goodreads user ID"
+                                prop:value=move || friend_input.get()
+                                on:input=move |e| friend_input.set(event_target_value(&e))
+                                title="A friend's Goodreads user ID or profile link; their profile has to be public"
+                            />
+                            <button type="submit" disabled=move || friend_input.get().trim().is_empty()>"Load their to-read shelf"</button>
+                        </form>
+                        {move || match friend_failed.get() {
+                            Some(reason) => view! { <span style="color: #d9534f;">{reason}</span> }.into_view(),
+                            // so their books aren't mistaken for your own
+                            None => friend_user_id.get().filter(|_| books_loaded.get()).map(|friend| view! {
+                                <span style="font-style: italic;">{format!("Showing {}'s to-read shelf, checked at your libraries", friend)}</span>
+                            }).into_view(),
+                        }}
+                        }.into_view(),
+                        _ => view! {
+                        <form
+                            style="display: flex; gap: 10px;"
+                            on:submit=move |e| {
+                                e.prevent_default();
+                                clear_books();
+                                fetch_books();
+                            }
+                        >
+                            <input
+                                type="text"
+                                placeholder="StoryGraph username"
+                                prop:value=move || storygraph_username.get()
+                                on:input=move |e| storygraph_username.set(event_target_value(&e))
+                                title="The username of a public StoryGraph profile"
+                            />
+                            <button type="submit" disabled=move || storygraph_username.get().trim().is_empty()>"Load to-read list"</button>
+                        </form>
+                        <Show when=move || storygraph_failed.get()>
+                            <span style="color: #d9534f;">"Couldn't load that StoryGraph to-read list. Check the username, and that the profile is public."</span>
+                        </Show>
+                        }.into_view(),
+                    }
+                >
                 <input
                     type="text"
                     placeholder="Goodreads user ID"
-                    value=user_id.get()
+                    prop:value=move || user_id_input.get()
                     on:input=move |e| {
-                        logging::log!("User ID input: {:?}", event_target_value(&e));
-                        user_id.update(|new_id| *new_id = event_target_value(&e));
+                        let input = event_target_value(&e);
+                        user_id_input.set(input.clone());
+                        // wait until typing stops, so "12345678" is one fetch instead of eight
+                        if let Some(pending) = pending_user_id.get_value() {
+                            pending.clear();
+                        }
+                        // a pasted profile or shelf link is boiled down to its id (and shelf)
+                        let Some(parsed) = goodreads::parse_user_input(&input) else {
+                            return;
+                        };
+                        let handle = set_timeout_with_handle(
+                            move || {
+                                logging::log!("User ID input: {:?}", parsed);
+                                requested_shelf.set(parsed.shelf);
+                                if user_id.get_untracked() != parsed.user_id {
+                                    user_id.set(parsed.user_id);
+                                }
+                            },
+                            USER_ID_DEBOUNCE,
+                        );
+                        pending_user_id.set_value(handle.ok());
                     }
-                    title="Goodreads user ID"
+                    title="Goodreads user ID, or a link to your Goodreads profile or shelf"
                 />
+                <Show when=invalid_user_id>
+                    <span style="color: #d9534f;">"That doesn't look like a Goodreads user ID or profile link"</span>
+                </Show>
+                // ctrl/cmd-click to combine several shelves into one list
                 <select
-                    on:input=move |e| {
-                        selected_shelf.set(event_target_value(&e));
+                    multiple
+                    title="Goodreads shelves"
+                    on:change=move |e| {
+                        let options = event_target::<web_sys::HtmlSelectElement>(&e).selected_options();
+                        let chosen = (0..options.length())
+                            .filter_map(|index| options.item(index))
+                            .filter_map(|option| option.get_attribute("value"))
+                            .collect::<Vec<_>>();
+                        selected_shelves.set(chosen);
                     }
                 >
-                    <option value="">"Select a shelf"</option>
                     {move || {
                         shelves.get().iter().map(|shelf| {
                             view! {
-                                <option value={shelf.clone()} selected={*shelf == "to-read"}>{shelf.clone()}</option>
+                                <option value={shelf.clone()} selected={selected_shelves.get().contains(shelf)}>{shelf.clone()}</option>
                             }
                         }).collect::<Vec<_>>()
                     }}
                 </select>
+                {STANDARD_SHELVES.into_iter().map(|shelf| view! {
+                    <button
+                        disabled=move || user_id.get().is_empty() || selected_shelves.get() == [shelf]
+                        on:click=move |_| {
+                            search_when_loaded.set(true);
+                            selected_shelves.set(vec![shelf.to_string()]);
+                        }
+                    >{shelf}</button>
+                }).collect::<Vec<_>>()}
+                // shared reads: someone else's same shelves, combined with these
+                <input
+                    type="text"
+                    placeholder="Second Goodreads user ID (optional)"
+                    prop:value=move || partner_input.get()
+                    on:input=move |e| partner_input.set(event_target_value(&e))
+                    // on change rather than input, so the shelves aren't re-read per keystroke
+                    on:change=move |e| {
+                        let partner = goodreads::parse_user_input(&event_target_value(&e))
+                            .map(|parsed| parsed.user_id);
+                        if partner_user_id.get_untracked() != partner {
+                            partner_user_id.set(partner);
+                        }
+                    }
+                    title="Someone else's Goodreads user ID or profile link, to find books on both of your shelves"
+                />
+                <select
+                    title="Which books of the two users to check"
+                    disabled=move || partner_user_id.get().is_none()
+                    on:change=move |e| shelf_combination.set(if event_target_value(&e) == "either" {
+                        goodreads::ShelfCombination::Either
+                    } else {
+                        goodreads::ShelfCombination::Both
+                    })
+                >
+                    <option value="both" selected=move || shelf_combination.get() == goodreads::ShelfCombination::Both>"On both shelves"</option>
+                    <option value="either" selected=move || shelf_combination.get() == goodreads::ShelfCombination::Either>"On either shelf"</option>
+                </select>
+                <Show when=move || partner_failed.get()>
+                    <span style="color: #d9534f;">"Couldn't read the second user's shelves (is the profile public?), showing only yours."</span>
+                </Show>
+                </Show>
+                <fieldset style="display: flex; gap: 10px; margin: 0;">
+                    <legend>"Formats"</legend>
+                    {BookFormat::ALL.into_iter().map(|format| view! {
+                        <label>
+                            <input
+                                type="checkbox"
+                                prop:checked=move || selected_formats.get().contains(&format)
+                                on:change=move |e| {
+                                    let checked = event_target_checked(&e);
+                                    selected_formats.update(|formats| {
+                                        formats.retain(|selected| *selected != format);
+                                        if checked {
+                                            formats.push(format);
+                                        }
+                                        // keep the columns in a stable order
+                                        formats.sort_by_key(|selected| {
+                                            BookFormat::ALL.iter().position(|f| f == selected)
+                                        });
+                                    });
+                                }
+                            />
+                            {format.label()}
+                        </label>
+                    }).collect_view()}
+                </fieldset>
+                // ctrl/cmd-click to pick several, or none to search every language
+                <select
+                    multiple
+                    title="Only find editions in these languages. Select none for any language."
+                    on:change=move |e| {
+                        let options = event_target::<web_sys::HtmlSelectElement>(&e).selected_options();
+                        let chosen = (0..options.length())
+                            .filter_map(|index| options.item(index))
+                            .filter_map(|option| option.get_attribute("value"))
+                            .collect::<Vec<_>>();
+                        selected_languages.set(chosen);
+                    }
+                >
+                    {OVERDRIVE_LANGUAGES.into_iter().map(|(code, label)| view! {
+                        <option value={code} selected=move || selected_languages.get().iter().any(|selected| selected == code)>{label}</option>
+                    }).collect_view()}
+                </select>
+                <label title="Leave out titles that aren't released yet, which can be held but not borrowed">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || exclude_preorders.get()
+                        on:change=move |e| exclude_preorders.set(event_target_checked(&e))
+                    />
+                    "Exclude pre-orders"
+                </label>
+                <label title="How closely a library's title and author have to match the book. Exact finds fewer editions, fuzzy may find a different book.">
+                    "Matching "
+                    <select on:change=move |e| {
+                        if let Some(strictness) = MatchStrictness::from_param(&event_target_value(&e)) {
+                            match_strictness.set(strictness);
+                        }
+                    }>
+                        {MatchStrictness::ALL.into_iter().map(|strictness| view! {
+                            <option value={strictness.param()} selected=move || match_strictness.get() == strictness>
+                                {strictness.label()}
+                            </option>
+                        }).collect_view()}
+                    </select>
+                </label>
+                <label title="How many books to check at the same time. Lower it if searches start failing.">
+                    "Parallel checks "
+                    <input
+                        type="number"
+                        min=*CHECK_CONCURRENCY_RANGE.start()
+                        max=*CHECK_CONCURRENCY_RANGE.end()
+                        style="width: 4em;"
+                        prop:value=move || check_concurrency.get().to_string()
+                        on:change=move |e| {
+                            // out of range or garbage snaps back into the allowed bounds
+                            let limit = event_target_value(&e)
+                                .trim()
+                                .parse::<usize>()
+                                .unwrap_or(DEFAULT_CHECK_CONCURRENCY)
+                                .clamp(*CHECK_CONCURRENCY_RANGE.start(), *CHECK_CONCURRENCY_RANGE.end());
+                            check_concurrency.set(limit);
+                            // the input keeps the typed text unless the value actually changed
+                            event_target::<web_sys::HtmlInputElement>(&e).set_value(&limit.to_string());
+                        }
+                    />
+                </label>
             </div>
             {
                 move || {
-                let goodreads_url = format!("https://goodreads.com/review/list/{}?shelf=to-read", user_id.get());
-                if user_id.get().is_empty() {
+                let mut shelves = selected_shelves.get();
+                if shelves.is_empty() {
+                    shelves.push(DEFAULT_SHELF.to_string());
+                }
+                if book_source.get() != BookSource::Goodreads {
+                    view! { <div></div> }
+                } else if user_id.get().is_empty() {
                     view! {
                     <div>
                         <p>"Enter your Goodreads user ID to get started. "
@@ -1102,10 +2617,15 @@ fn HomePage() -> impl IntoView {
                 } else {
                     view! {
                     <div>
-                        <p>
-                            "Verify your Goodreads to-read shelf: "
-                            <a href={goodreads_url.clone()} target="_blank">{goodreads_url}</a>
-                        </p>
+                        {shelves.into_iter().map(|shelf| {
+                            let goodreads_url = format!("https://goodreads.com/review/list/{}?shelf={}", user_id.get(), shelf);
+                            view! {
+                            <p>
+                                {format!("Verify your Goodreads {} shelf: ", shelf)}
+                                <a href={goodreads_url.clone()} target="_blank">{goodreads_url}</a>
+                            </p>
+                            }
+                        }).collect::<Vec<_>>()}
                         <hr />
                     </div>
                     }
@@ -1117,16 +2637,224 @@ fn HomePage() -> impl IntoView {
                     <LibrarySearch search_libraries=search_libraries set_search_libraries=set_search_libraries selected_library_website_ids=selected_library_website_ids />
                 </div>
                 <div>
-                    <DisplaySelectedLibraries selected_libraries=selected_libraries selected_library_website_ids=selected_library_website_ids/>
+                    <DisplaySelectedLibraries selected_libraries=selected_libraries selected_library_website_ids=selected_library_website_ids selected_branches=selected_branches hoopla_libraries=hoopla_libraries/>
                 </div>
             </div>
-            <button on:click=move |_| fetch_availability()>"Search"</button>
+            <div style="display: flex; gap: 10px; flex-wrap: wrap;">
+                <button
+                    disabled=move || selected_libraries.get().is_empty() || running_checks.get() != 0
+                    title=move || if selected_libraries.get().is_empty() { "Add a library first" } else { "" }
+                    on:click=move |_| fetch_availability()
+                >"Search"</button>
+                <button
+                    disabled=move || running_checks.get() == 0
+                    on:click=move |_| cancel_checks()
+                >"Cancel"</button>
+                // re-reads the shelf without discarding results, so newly-added books can be checked
+                <button
+                    disabled=move || match book_source.get() {
+                        BookSource::Goodreads => selected_shelves.get().is_empty(),
+                        BookSource::StoryGraph => storygraph_username.get().trim().is_empty(),
+                        BookSource::GoodreadsExport => true,
+                        BookSource::Pasted => pasted_books.get().trim().is_empty(),
+                        BookSource::FriendShelf => friend_input.get().trim().is_empty(),
+                    }
+                    on:click=move |_| fetch_books()
+                >"Reload shelf"</button>
+                <button
+                    disabled=move || availability.get().is_empty() || unchecked_books().is_empty()
+                    on:click=move |_| fetch_new_availability()
+                >{move || format!("Check new books only ({})", unchecked_books().len())}</button>
+                <button
+                    disabled=move || unsuccessful_books().is_empty()
+                    on:click=move |_| recheck_unsuccessful()
+                >{move || format!("Re-check not owned ({})", unsuccessful_books().len())}</button>
+            </div>
             // display summary of availability and progress bar
             <div>
-                <p>{move || format!("Available: {}, Holdable: {}, Not Owned: {} -- {}/{}", available_count.get(), holdable_count.get(), not_owned_count.get(), libby_progress.get(), books.get().len())}</p>
+                <p>{move || format!("Available: {}, Holdable: {}, Not Owned: {}, Unknown: {} -- {}/{}", available_count.get(), holdable_count.get(), not_owned_count.get(), unknown_count.get(), libby_progress.get(), books.get().len())}</p>
                 <progress style="width: 95%;" value=libby_progress max={move || books.get().len()}></progress>
+                // the text above changes with every book, this is the throttled version for screen readers
+                <p class="visually-hidden" aria-live="polite">{move || progress_announcement.get()}</p>
+                <p>{move || {
+                    let done = libby_progress.get().max(0) as usize;
+                    let elapsed_ms = search_started_at
+                        .get()
+                        .map(|started_at| js_sys::Date::now() - started_at);
+                    progress_label(done, books.get().len(), elapsed_ms)
+                }}</p>
+                {move || last_checked.get().map(|checked_at| {
+                    let checked_at = js_sys::Date::new(&JsValue::from_f64(checked_at))
+                        .to_locale_string("default", &JsValue::UNDEFINED);
+                    view! {
+                        <p>
+                            {format!("Last checked {} ", String::from(checked_at))}
+                            <button
+                                disabled=move || running_checks.get() != 0
+                                on:click=move |_| fetch_availability()
+                            >"Refresh"</button>
+                        </p>
+                    }
+                })}
+            </div>
+            // citation exports of the shelf, for reference managers
+            <div style="display: flex; gap: 10px;">
+                <button
+                    disabled=move || books.get().is_empty()
+                    on:click=move |_| export::download(
+                        &format!("{}.ris", selected_shelves.get().join("+")),
+                        "application/x-research-info-systems",
+                        &export::shelf_to_ris(&books.get()),
+                    )
+                >"Export RIS"</button>
+                <button
+                    disabled=move || books.get().is_empty()
+                    on:click=move |_| export::download(
+                        &format!("{}.bib", selected_shelves.get().join("+")),
+                        "application/x-bibtex",
+                        &export::shelf_to_bibtex(&books.get()),
+                    )
+                >"Export BibTeX"</button>
+                <button
+                    disabled=move || availability.get().is_empty()
+                    on:click=move |_| {
+                        let library_names = selected_libraries
+                            .get()
+                            .iter()
+                            .map(|library| library.search_library.system_name.clone())
+                            .collect::<Vec<_>>();
+                        export::download(
+                            &format!("{}-availability.csv", selected_shelves.get().join("+")),
+                            "text/csv",
+                            &export::availability_to_csv(
+                                &books.get(),
+                                &availability.get(),
+                                &library_names,
+                            ),
+                        )
+                    }
+                >"Export CSV"</button>
+                <button
+                    disabled=move || availability.get().is_empty()
+                    on:click=move |_| export::download(
+                        &format!("{}-availability.json", selected_shelves.get().join("+")),
+                        "application/json",
+                        &export::availability_to_json(&books.get(), &availability.get()),
+                    )
+                >"Export JSON"</button>
+            </div>
+            // re-checked on the server every few hours, with an email when something frees up
+            <div style="display: flex; gap: 10px; align-items: center;">
+                "Email me when a book becomes available:"
+                <input
+                    type="email"
+                    placeholder="you@example.com"
+                    prop:value=move || notify_email.get()
+                    on:input=move |e| notify_email.set(event_target_value(&e))
+                />
+                <button
+                    disabled=move || {
+                        notify_email.get().trim().is_empty()
+                            || user_id.get().is_empty()
+                            || selected_library_website_ids.get().is_empty()
+                    }
+                    on:click=move |_| {
+                        let email = notify_email.get_untracked();
+                        let user_id = user_id.get_untracked();
+                        let shelves = selected_shelves.get_untracked();
+                        let library_website_ids = selected_library_website_ids.get_untracked();
+                        let formats = selected_formats.get_untracked();
+                        notify_status.set(None);
+                        spawn_local(async move {
+                            let result = subscribe_to_notifications(
+                                email,
+                                user_id,
+                                shelves,
+                                library_website_ids,
+                                formats,
+                            )
+                            .await;
+                            notify_status.set(Some(result.map_err(|err| match err {
+                                ServerFnError::ServerError(message) => message,
+                                err => err.to_string(),
+                            })));
+                        });
+                    }
+                >"Subscribe"</button>
+                {move || match notify_status.get() {
+                    Some(Ok(())) => view! {
+                        <span>"Check your inbox and open the confirmation link to start the emails."</span>
+                    }.into_view(),
+                    Some(Err(message)) => view! {
+                        <span style="color: #d9534f;">{message}</span>
+                    }.into_view(),
+                    None => ().into_view(),
+                }}
             </div>
             <hr />
+            <div style="display: flex; gap: 10px; margin-bottom: 10px;">
+                <input
+                    type="search"
+                    placeholder="Filter by title or author"
+                    prop:value=move || search_text.get()
+                    on:input=move |e| search_text.set(event_target_value(&e))
+                />
+                <button
+                    title="Available first, then the shortest hold wait, then the longest on your shelf"
+                    disabled=move || sort_by.get() == "smart"
+                    on:click=move |_| {
+                        set_sort_by("smart".to_string());
+                        set_sort_order("asc".to_string());
+                    }
+                >"Smart sort"</button>
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=move || group_by_series.get()
+                        on:change=move |e| group_by_series.set(event_target_checked(&e))
+                    />
+                    "Group by series"
+                </label>
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=move || compact_table.get()
+                        on:change=move |e| compact_table.set(event_target_checked(&e))
+                    />
+                    "Compact rows"
+                </label>
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=move || show_publication.get()
+                        on:change=move |e| show_publication.set(event_target_checked(&e))
+                    />
+                    "Publisher & year"
+                </label>
+                "Show:"
+                {BookAvailability::ALL
+                    .into_iter()
+                    .map(|status| (Some(status), status.label()))
+                    .chain([(None, "Pending")])
+                    .map(|(status, label)| view! {
+                    <label>
+                        <input
+                            type="checkbox"
+                            prop:checked=move || availability_filter.get().contains(&status)
+                            on:change=move |e| {
+                                let checked = event_target_checked(&e);
+                                availability_filter.update(|filter| {
+                                    filter.retain(|shown| *shown != status);
+                                    if checked {
+                                        filter.push(status);
+                                    }
+                                });
+                            }
+                        />
+                        {label}
+                    </label>
+                }).collect_view()}
+            </div>
             // display books in a table if the user is not private
             {
                 move || {
@@ -1143,10 +2871,27 @@ fn HomePage() -> impl IntoView {
                         </p>
                     </div>
                     }
+                } else if books_loaded.get() && books.with(|books| books.is_empty()) {
+                    view! {
+                    <div>
+                        <p style="font-weight: bold;">
+                            {match book_source.get() {
+                                BookSource::Goodreads => format!(
+                                    "No books found on the {} shelf. Check the shelf name on Goodreads, or add some books to it.",
+                                    selected_shelves.get().join(" + "),
+                                ),
+                                BookSource::StoryGraph => "No books found on this StoryGraph to-read list.".to_string(),
+                                BookSource::GoodreadsExport => format!("No books on the {} shelf in this export.", DEFAULT_SHELF),
+                                BookSource::Pasted => "No titles or ISBNs to check.".to_string(),
+                                BookSource::FriendShelf => format!("No books on their {} shelf.", DEFAULT_SHELF),
+                            }}
+                        </p>
+                    </div>
+                    }
                 } else {
                     view! {
                         <div>
-                            <BookTable books=books availability=availability sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order />
+                            <BookTable books=books availability=availability sort_by=sort_by sort_order=sort_order set_sort_by=set_sort_by set_sort_order=set_sort_order formats=selected_formats availability_filter=availability_filter search_text=search_text on_recheck=recheck_book on_pin=pin_book group_by_series=group_by_series branches=selected_branches hoopla=hoopla_results show_hoopla=hoopla_enabled newly_available=newly_available compact=compact_table show_publication=show_publication />
                         </div>
                     }
                 }
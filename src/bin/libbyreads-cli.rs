@@ -0,0 +1,194 @@
+use futures::stream::{self, StreamExt, TryStreamExt};
+use libbyreads_rs::config::config;
+use libbyreads_rs::lookup::{self, BookAvailability, LibbyBook, MatchStrictness};
+use std::process::ExitCode;
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "\
+Checks a Goodreads shelf at Libby libraries and prints what's available.
+
+Usage: libbyreads-cli --user-id <id> --library <system_id> [--library <system_id>...]
+                      [--shelf <name>...] [--match <strictness>] [--json]
+
+  --user-id <id>          Goodreads user id, e.g. 12345678
+  --library <system_id>   Overdrive system id, e.g. hawaii (as in libbyapp.com/library/hawaii)
+  --shelf <name>          Goodreads shelf to read, to-read when none are given
+  --match <strictness>    exact, normalized or fuzzy (the default), how closely titles must match
+  --json                  print the results as JSON, the same shape as /api/availability";
+
+// longest title or author printed in the table before it's cut short
+const MAX_COLUMN_WIDTH: usize = 40;
+
+#[derive(Debug, Default)]
+struct Args {
+    user_id: String,
+    shelves: Vec<String>,
+    system_ids: Vec<String>,
+    strictness: MatchStrictness,
+    json: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        // both "--shelf read" and "--shelf=read"
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag.as_str() {
+            "--user-id" => parsed.user_id = value()?.trim().to_string(),
+            "--shelf" => parsed.shelves.push(value()?),
+            "--library" => parsed.system_ids.push(value()?),
+            "--match" => {
+                let strictness = value()?;
+                parsed.strictness = MatchStrictness::from_param(&strictness)
+                    .ok_or_else(|| format!("Unknown --match {}", strictness))?;
+            }
+            "--json" => parsed.json = true,
+            other => return Err(format!("Unknown argument {}", other)),
+        }
+    }
+    if parsed.user_id.is_empty() {
+        return Err("--user-id is required".to_string());
+    }
+    if parsed.system_ids.is_empty() {
+        return Err("at least one --library is required".to_string());
+    }
+    Ok(parsed)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    // logs go to stderr so stdout stays pipeable, and only warnings unless RUST_LOG says otherwise
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let args = match parse_args(args.into_iter()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args).await {
+        Ok(availability) => {
+            if args.json {
+                match serde_json::to_string_pretty(&availability) {
+                    Ok(json) => println!("{}", json),
+                    Err(err) => {
+                        eprintln!("Could not serialize the results: {}", err);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            } else {
+                print_table(&availability);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// the same steps as `/api/availability`, without the background refresh
+async fn run(args: &Args) -> Result<Vec<LibbyBook>, String> {
+    let libraries = stream::iter(&args.system_ids)
+        .then(|system_id| lookup::library_from_system_id(system_id))
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|err| err.to_string())?;
+    let books = lookup::goodreads_books(&args.user_id, &args.shelves)
+        .await
+        .map_err(|err| err.to_string())?;
+    stream::iter(&books)
+        .map(|book| {
+            lookup::libby_availability(
+                book,
+                &libraries,
+                &[],
+                &[],
+                true,
+                args.strictness,
+                None,
+                false,
+            )
+        })
+        .buffered(config().api_max_concurrent_books)
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|err| err.to_string())
+}
+
+fn print_table(availability: &[LibbyBook]) {
+    let cell = |text: &str| {
+        if text.chars().count() > MAX_COLUMN_WIDTH {
+            let cut = text.chars().take(MAX_COLUMN_WIDTH - 1).collect::<String>();
+            format!("{}…", cut)
+        } else {
+            text.to_string()
+        }
+    };
+    let rows = availability
+        .iter()
+        .map(|libby_book| {
+            let details = match libby_book.availability {
+                BookAvailability::Available => libby_book
+                    .available_copies
+                    .map(|copies| format!("{} copies", copies))
+                    .unwrap_or_default(),
+                BookAvailability::Holdable => libby_book
+                    .estimated_wait_days
+                    .map(|days| format!("~{} days", days))
+                    .unwrap_or_default(),
+                BookAvailability::NotOwned | BookAvailability::Unknown => String::new(),
+            };
+            [
+                cell(&libby_book.title),
+                cell(&libby_book.author),
+                libby_book.availability.label().to_string(),
+                details,
+                libby_book.libby_search_url.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header = ["Title", "Author", "Status", "Wait", "Link"].map(str::to_string);
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| {
+                let padding = width - column.chars().count();
+                format!("{}{}", column, " ".repeat(padding))
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
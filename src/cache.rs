@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// past this many entries, inserting sweeps out the expired ones first
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// A process-wide map whose entries expire `ttl` after they were inserted.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A copy of the cached value, if there is one that hasn't expired yet.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .get(key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if entries.len() >= SWEEP_THRESHOLD {
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    /// Changes a value in place, starting from the default if there's none or it expired, and
+    /// restarts its TTL. Done under the lock, so concurrent updates don't lose each other.
+    pub fn update(&self, key: K, change: impl FnOnce(&mut V))
+    where
+        V: Default,
+    {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if entries.len() >= SWEEP_THRESHOLD {
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        }
+        let entry = entries
+            .entry(key)
+            .or_insert_with(|| (Instant::now(), V::default()));
+        if entry.0.elapsed() >= self.ttl {
+            entry.1 = V::default();
+        }
+        entry.0 = Instant::now();
+        change(&mut entry.1);
+    }
+
+    /// Takes a value out, if there is one that hasn't expired yet.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .remove(key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, value)| value)
+    }
+
+    /// Every key that hasn't expired yet, in no particular order.
+    pub fn keys(&self) -> Vec<K> {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .iter()
+            .filter(|(_, (inserted, _))| inserted.elapsed() < self.ttl)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        cache.insert("dune", 1);
+        assert_eq!(cache.get(&"dune"), Some(1));
+        assert_eq!(cache.get(&"emma"), None);
+        assert_eq!(cache.keys(), vec!["dune"]);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&"dune"), None);
+        assert!(cache.keys().is_empty());
+    }
+
+    #[test]
+    fn inserting_again_replaces_the_value_and_restarts_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        cache.insert("dune", 1);
+        std::thread::sleep(Duration::from_millis(30));
+        cache.insert("dune", 2);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&"dune"), Some(2));
+    }
+
+    #[test]
+    fn updates_add_up_until_the_value_is_removed() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        cache.update("dune", |count: &mut i32| *count += 1);
+        cache.update("dune", |count| *count += 1);
+        assert_eq!(cache.remove(&"dune"), Some(2));
+        assert_eq!(cache.remove(&"dune"), None);
+
+        cache.update("dune", |count| *count += 1);
+        std::thread::sleep(Duration::from_millis(60));
+        cache.update("dune", |count| *count += 1);
+        assert_eq!(cache.get(&"dune"), Some(1));
+    }
+}
@@ -0,0 +1,148 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Server-side tuning knobs, read once from the environment (or `.env`).
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How many Goodreads shelf pages `get_goodreads_books` fetches at the same time.
+    pub goodreads_max_concurrent_pages: usize,
+    /// How many books `/api/availability` checks against the libraries at the same time.
+    pub api_max_concurrent_books: usize,
+    /// How long a book's Overdrive results at a library are reused before searching again.
+    pub overdrive_cache_ttl: Duration,
+    /// How many pages of title + author results we scan for a match before giving up.
+    pub overdrive_max_pages: usize,
+    /// The SQLite database each email subscription's last-known book statuses are saved in,
+    /// kept in memory only when unset.
+    pub status_store_path: Option<PathBuf>,
+    /// The mail server availability notifications are sent through, None to turn them off.
+    pub smtp: Option<SmtpConfig>,
+    /// Where email subscriptions are saved, kept in memory only when unset.
+    pub subscriptions_path: Option<PathBuf>,
+    /// How often subscribed shelves are checked again for newly available books.
+    pub notify_interval: Duration,
+    /// The address this site is reached at, e.g. "https://libbyreads.example.com", for the
+    /// unsubscribe links in emails.
+    pub public_url: String,
+    /// How often the shelves of confirmed email subscriptions are checked again in the
+    /// background. API requests don't add shelves, they only reuse a recent check.
+    pub refresh_interval: Duration,
+    /// How many of those shelves are refreshed at the same time.
+    pub refresh_max_concurrent: usize,
+}
+
+/// An SMTP server reached over implicit TLS on port 465 or STARTTLS on any other port (e.g.
+/// 587), with a username and password.
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    /// e.g. "libbyreads <noreply@example.com>"
+    pub from: String,
+}
+
+// keeps the password out of logs
+impl std::fmt::Debug for SmtpConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmtpConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("from", &self.from)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SmtpConfig {
+    // every setting but the port is required
+    fn from_env() -> Option<Self> {
+        let var = |key: &str| env::var(key).ok().filter(|value| !value.trim().is_empty());
+        Some(SmtpConfig {
+            host: var("SMTP_HOST")?,
+            port: env_usize("SMTP_PORT")
+                .and_then(|port| u16::try_from(port).ok())
+                .unwrap_or(465),
+            username: var("SMTP_USERNAME")?,
+            password: var("SMTP_PASSWORD")?,
+            from: var("EMAIL_FROM")?,
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            goodreads_max_concurrent_pages: 4,
+            api_max_concurrent_books: 5,
+            overdrive_cache_ttl: Duration::from_secs(6 * 60 * 60),
+            overdrive_max_pages: 3,
+            status_store_path: None,
+            smtp: None,
+            subscriptions_path: None,
+            notify_interval: Duration::from_secs(6 * 60 * 60),
+            public_url: "http://127.0.0.1:3000".to_string(),
+            refresh_interval: Duration::from_secs(60 * 60),
+            refresh_max_concurrent: 2,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+        Config {
+            goodreads_max_concurrent_pages: env_usize("GOODREADS_MAX_CONCURRENT_PAGES")
+                .unwrap_or(defaults.goodreads_max_concurrent_pages),
+            api_max_concurrent_books: env_usize("API_MAX_CONCURRENT_BOOKS")
+                .unwrap_or(defaults.api_max_concurrent_books),
+            overdrive_cache_ttl: env_usize("OVERDRIVE_CACHE_TTL_SECS")
+                .map(|secs| Duration::from_secs(secs as u64))
+                .unwrap_or(defaults.overdrive_cache_ttl),
+            overdrive_max_pages: env_usize("OVERDRIVE_MAX_PAGES")
+                .unwrap_or(defaults.overdrive_max_pages),
+            status_store_path: env_path("STATUS_STORE_PATH").or(defaults.status_store_path),
+            smtp: SmtpConfig::from_env(),
+            subscriptions_path: env_path("SUBSCRIPTIONS_PATH").or(defaults.subscriptions_path),
+            notify_interval: env_usize("NOTIFY_INTERVAL_SECS")
+                .map(|secs| Duration::from_secs(secs as u64))
+                .unwrap_or(defaults.notify_interval),
+            public_url: env::var("PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim().trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty())
+                .unwrap_or(defaults.public_url),
+            refresh_interval: env_usize("REFRESH_INTERVAL_SECS")
+                .map(|secs| Duration::from_secs(secs as u64))
+                .unwrap_or(defaults.refresh_interval),
+            refresh_max_concurrent: env_usize("REFRESH_MAX_CONCURRENT")
+                .unwrap_or(defaults.refresh_max_concurrent),
+        }
+    }
+}
+
+// Parses a positive integer env var, ignoring unset, malformed, or zero values.
+fn env_usize(key: &str) -> Option<usize> {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|value| *value > 0)
+}
+
+// A file path env var, ignoring unset or blank values.
+fn env_path(key: &str) -> Option<PathBuf> {
+    env::var(key)
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The process-wide config, loaded from the environment on first use.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
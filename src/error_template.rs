@@ -1,5 +1,6 @@
 use http::status::StatusCode;
 use leptos::*;
+use std::str::FromStr;
 use thiserror::Error;
 
 #[derive(Clone, Debug, Error)]
@@ -16,6 +17,152 @@ impl AppError {
     }
 }
 
+/// Why reading a Goodreads profile or shelf failed. Travels across the server function boundary as its
+/// display string, so the client can match on the variant instead of the message.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum GoodreadsError {
+    #[error("Private profile")]
+    PrivateProfile,
+    #[error("Goodreads user not found")]
+    NotFound,
+    #[error("Goodreads blocked the request")]
+    Blocked,
+    #[error("Goodreads request failed: {0}")]
+    Request(String),
+}
+
+impl FromStr for GoodreadsError {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Private profile" => GoodreadsError::PrivateProfile,
+            "Goodreads user not found" => GoodreadsError::NotFound,
+            "Goodreads blocked the request" => GoodreadsError::Blocked,
+            other => GoodreadsError::Request(
+                other
+                    .strip_prefix("Goodreads request failed: ")
+                    .unwrap_or(other)
+                    .to_string(),
+            ),
+        })
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<reqwest::Error> for GoodreadsError {
+    fn from(err: reqwest::Error) -> Self {
+        GoodreadsError::Request(err.to_string())
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<tokio::task::JoinError> for GoodreadsError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        GoodreadsError::Request(err.to_string())
+    }
+}
+
+/// Why checking a book's availability failed outright, rather than leaving it unknown.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum AvailabilityError {
+    #[error("No libraries selected")]
+    NoLibraries,
+    #[error("Malformed Overdrive response")]
+    Malformed,
+    #[error("Availability check failed: {0}")]
+    Request(String),
+}
+
+impl FromStr for AvailabilityError {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "No libraries selected" => AvailabilityError::NoLibraries,
+            "Malformed Overdrive response" => AvailabilityError::Malformed,
+            other => AvailabilityError::Request(
+                other
+                    .strip_prefix("Availability check failed: ")
+                    .unwrap_or(other)
+                    .to_string(),
+            ),
+        })
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<reqwest::Error> for AvailabilityError {
+    fn from(err: reqwest::Error) -> Self {
+        AvailabilityError::Request(err.to_string())
+    }
+}
+
+/// Why looking up a library failed.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum LibraryError {
+    #[error("Library not found: {0}")]
+    NotFound(String),
+    #[error("Malformed library search response")]
+    Malformed,
+    #[error("Library lookup failed: {0}")]
+    Request(String),
+}
+
+#[cfg(feature = "ssr")]
+impl From<reqwest::Error> for LibraryError {
+    fn from(err: reqwest::Error) -> Self {
+        LibraryError::Request(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for LibraryError {
+    fn from(_: serde_json::Error) -> Self {
+        LibraryError::Malformed
+    }
+}
+
+/// Why reading a StoryGraph to-read list failed.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum StorygraphError {
+    #[error("StoryGraph username is required")]
+    MissingUsername,
+    #[error("StoryGraph user not found: {0}")]
+    NotFound(String),
+    #[error("StoryGraph request failed: {0}")]
+    Request(String),
+}
+
+#[cfg(feature = "ssr")]
+impl From<reqwest::Error> for StorygraphError {
+    fn from(err: reqwest::Error) -> Self {
+        StorygraphError::Request(err.to_string())
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<tokio::task::JoinError> for StorygraphError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        StorygraphError::Request(err.to_string())
+    }
+}
+
+/// Why a Hoopla catalog search failed.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum HooplaError {
+    #[error("Malformed Hoopla search response")]
+    Malformed,
+    #[error("Hoopla search failed: {0}")]
+    Request(String),
+}
+
+#[cfg(feature = "ssr")]
+impl From<reqwest::Error> for HooplaError {
+    fn from(err: reqwest::Error) -> Self {
+        HooplaError::Request(err.to_string())
+    }
+}
+
 // A basic function to display errors served by the error boundaries.
 // Feel free to do more complicated things here than just displaying the error.
 #[component]
@@ -0,0 +1,323 @@
+use crate::types::{BookAvailability, GoodreadsBook, LibbyBook};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use leptos::document;
+use serde_json::{json, Value};
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Formats a shelf as an RIS file, which Zotero, EndNote, Mendeley and friends can import.
+pub fn shelf_to_ris(books: &[GoodreadsBook]) -> String {
+    let mut ris = String::new();
+    for book in books {
+        ris.push_str("TY  - BOOK\r\n");
+        ris.push_str(&format!("TI  - {}\r\n", book.title));
+        if !book.author.is_empty() {
+            ris.push_str(&format!("AU  - {}\r\n", book.author));
+        }
+        if let Some(isbn) = &book.isbn {
+            ris.push_str(&format!("SN  - {}\r\n", isbn));
+        }
+        ris.push_str("ER  - \r\n\r\n");
+    }
+    ris
+}
+
+/// Formats a shelf as a list of BibTeX `@book` entries.
+pub fn shelf_to_bibtex(books: &[GoodreadsBook]) -> String {
+    let mut bibtex = String::new();
+    for (index, book) in books.iter().enumerate() {
+        bibtex.push_str(&format!("@book{{{},\n", bibtex_key(book, index)));
+        bibtex.push_str(&format!("  title = {{{}}},\n", bibtex_escape(&book.title)));
+        if !book.author.is_empty() {
+            bibtex.push_str(&format!(
+                "  author = {{{}}},\n",
+                bibtex_escape(&book.author)
+            ));
+        }
+        if let Some(isbn) = &book.isbn {
+            bibtex.push_str(&format!("  isbn = {{{}}},\n", isbn));
+        }
+        bibtex.push_str("}\n\n");
+    }
+    bibtex
+}
+
+// e.g. "Brandon Sanderson", "The Way of Kings" -> sanderson_the_0
+// the index keeps keys unique when an author has several books starting with the same word
+fn bibtex_key(book: &GoodreadsBook, index: usize) -> String {
+    let slug = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase()
+    };
+    let author = slug(book.author.split_whitespace().last().unwrap_or("anon"));
+    let title = slug(book.title.split_whitespace().next().unwrap_or("untitled"));
+    format!("{}_{}_{}", author, title, index)
+}
+
+fn bibtex_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '&' | '%' | '$' | '#' | '_' | '{' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats the availability results as CSV, one row per shelf book in shelf order, with a
+/// column per library. Books still being checked are marked "pending".
+pub fn availability_to_csv(
+    books: &[GoodreadsBook],
+    availability: &[LibbyBook],
+    library_names: &[String],
+) -> String {
+    let mut header = vec![
+        "title",
+        "author",
+        "status",
+        "is_available",
+        "is_holdable",
+        "libby_search_url",
+    ];
+    header.extend(library_names.iter().map(|name| name.as_str()));
+    let mut csv = csv_row(header);
+
+    for book in books {
+        let Some(libby_book) = availability
+            .iter()
+            .find(|libby_book| libby_book.is_for(book))
+        else {
+            let mut row = vec![
+                book.title.as_str(),
+                book.author.as_str(),
+                "pending",
+                "",
+                "",
+                "",
+            ];
+            row.extend(library_names.iter().map(|_| "pending"));
+            csv.push_str(&csv_row(row));
+            continue;
+        };
+        let is_available = libby_book.is_available.to_string();
+        let is_holdable = libby_book.is_holdable.to_string();
+        let mut row = vec![
+            libby_book.title.as_str(),
+            libby_book.author.as_str(),
+            overall_status(libby_book.availability),
+            &is_available,
+            &is_holdable,
+            &libby_book.libby_search_url,
+        ];
+        // a library counts as available if any of its formats is
+        row.extend(library_names.iter().map(|name| {
+            let at_library = || {
+                libby_book
+                    .library_books
+                    .iter()
+                    .filter(|library_book| &library_book.library == name)
+            };
+            status(
+                at_library().any(|library_book| library_book.is_available),
+                at_library().any(|library_book| library_book.is_holdable),
+            )
+        }));
+        csv.push_str(&csv_row(row));
+    }
+    csv
+}
+
+/// Formats the availability results as a JSON array, one entry per shelf book in shelf order.
+/// A checked book is its full `LibbyBook`, including the per-library `library_books`, plus a
+/// `status` matching the CSV's; a book still being checked is just its title and author with
+/// `"status": "pending"`.
+pub fn availability_to_json(books: &[GoodreadsBook], availability: &[LibbyBook]) -> String {
+    let entries = books
+        .iter()
+        .map(|book| {
+            let Some(libby_book) = availability
+                .iter()
+                .find(|libby_book| libby_book.is_for(book))
+            else {
+                return json!({
+                    "status": "pending",
+                    "title": book.title,
+                    "author": book.author,
+                });
+            };
+            let mut entry = serde_json::to_value(libby_book).unwrap_or_else(|_| json!({}));
+            if let Value::Object(fields) = &mut entry {
+                fields.insert(
+                    "status".to_string(),
+                    overall_status(libby_book.availability).into(),
+                );
+            }
+            entry
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&entries).unwrap_or_default()
+}
+
+/// Formats an iCalendar feed with an all-day event per holdable book that has a wait estimate,
+/// on the day the shortest hold queue among its libraries should reach the reader.
+pub fn holds_to_ics(availability: &[LibbyBook], today: NaiveDate, now: DateTime<Utc>) -> String {
+    let mut ics = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//libbyreads//Hold estimates//EN",
+        "CALSCALE:GREGORIAN",
+        "X-WR-CALNAME:Libby holds",
+    ] {
+        ics_line(&mut ics, line);
+    }
+    let stamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    for libby_book in availability {
+        let Some((library_book, wait_days)) = libby_book
+            .library_books
+            .iter()
+            .filter(|library_book| library_book.is_holdable && !library_book.is_available)
+            .filter_map(|library_book| Some((library_book, library_book.estimated_wait_days?)))
+            .min_by_key(|(_, wait_days)| *wait_days)
+        else {
+            continue;
+        };
+        let Some(ready) = today.checked_add_days(Days::new(wait_days.max(0) as u64)) else {
+            continue;
+        };
+        let uid = format!(
+            "{}-{}@libbyreads",
+            library_book
+                .title_id
+                .clone()
+                .unwrap_or_else(|| uid_slug(&libby_book.title)),
+            uid_slug(&library_book.library),
+        );
+        ics_line(&mut ics, "BEGIN:VEVENT");
+        ics_line(&mut ics, &format!("UID:{}", uid));
+        ics_line(&mut ics, &format!("DTSTAMP:{}", stamp));
+        ics_line(
+            &mut ics,
+            &format!("DTSTART;VALUE=DATE:{}", ready.format("%Y%m%d")),
+        );
+        ics_line(
+            &mut ics,
+            &format!(
+                "DTEND;VALUE=DATE:{}",
+                ready.succ_opt().unwrap_or(ready).format("%Y%m%d")
+            ),
+        );
+        ics_line(
+            &mut ics,
+            &format!(
+                "SUMMARY:{}",
+                ics_escape(&format!(
+                    "{} hold ready at {} (estimate)",
+                    libby_book.title, library_book.library
+                ))
+            ),
+        );
+        ics_line(
+            &mut ics,
+            &format!(
+                "DESCRIPTION:{}",
+                ics_escape(&format!(
+                    "Estimated {} day wait as of {}. Place the hold in Libby: {}",
+                    wait_days, today, library_book.libby_search_url
+                ))
+            ),
+        );
+        ics_line(&mut ics, &format!("URL:{}", library_book.libby_search_url));
+        ics_line(&mut ics, "END:VEVENT");
+    }
+    ics_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+// "Hawaii State Public Library System" -> "hawaiistatepubliclibrarysystem", for event ids
+fn uid_slug(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// per RFC 5545 text values escape \ ; , and newlines
+fn ics_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// appends a content line, folded so no line is longer than 75 bytes
+fn ics_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn overall_status(availability: BookAvailability) -> &'static str {
+    match availability {
+        BookAvailability::Available => "available",
+        BookAvailability::Holdable => "holdable",
+        BookAvailability::NotOwned => "not owned",
+        BookAvailability::Unknown => "unknown",
+    }
+}
+
+fn status(is_available: bool, is_holdable: bool) -> &'static str {
+    if is_available {
+        "available"
+    } else if is_holdable {
+        "holdable"
+    } else {
+        "not owned"
+    }
+}
+
+// quotes fields that contain a delimiter, per RFC 4180
+fn csv_row(fields: Vec<&str>) -> String {
+    let fields = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Hands `contents` to the browser as a file download, without a server round-trip.
+pub fn download(filename: &str, mime_type: &str, contents: &str) {
+    let parts = js_sys::Array::of1(&JsValue::from_str(contents));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let Ok(blob) = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options) else {
+        return;
+    };
+    let Ok(url) = web_sys::Url::create_object_url_with_blob(&blob) else {
+        return;
+    };
+    if let Ok(element) = document().create_element("a") {
+        let anchor: web_sys::HtmlAnchorElement = element.unchecked_into();
+        anchor.set_href(&url);
+        anchor.set_download(filename);
+        anchor.click();
+    }
+    let _ = web_sys::Url::revoke_object_url(&url);
+}
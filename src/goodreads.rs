@@ -0,0 +1,394 @@
+use crate::matching::{normalize_author, normalize_title};
+use crate::types::GoodreadsBook;
+use chrono::NaiveDate;
+use std::collections::HashSet;
+
+/// What a pasted Goodreads link (or a bare id) points at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoodreadsInput {
+    pub user_id: String,
+    pub shelf: Option<String>,
+}
+
+/// Reads a Goodreads user id, and a shelf if there is one, out of what was typed or pasted
+/// into the user id box. Accepts bare ids ("12345678", "12345678-jane") and profile, shelf
+/// and app share links, e.g.
+/// - https://www.goodreads.com/review/list/12345678-jane?shelf=to-read
+/// - https://www.goodreads.com/user/show/12345678-jane
+/// - https://goodreads.com/user/show/12345678?utm_medium=api&utm_source=app_share
+pub fn parse_user_input(input: &str) -> Option<GoodreadsInput> {
+    let input = input.trim();
+    if !input.contains("goodreads.com") {
+        return leading_id(input).map(|user_id| GoodreadsInput {
+            user_id,
+            shelf: None,
+        });
+    }
+
+    let (path, query) = match input.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (input, None),
+    };
+    let query = query.map(|query| query.split('#').next().unwrap_or(query));
+    // the id is the segment after /review/list/ or /user/show/
+    let segments = path.split('/').collect::<Vec<_>>();
+    let user_id = segments
+        .windows(3)
+        .find(|window| {
+            matches!(
+                (window[0], window[1]),
+                ("review", "list") | ("user", "show")
+            )
+        })
+        .and_then(|window| leading_id(window[2]))?;
+    let shelf = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|param| param.strip_prefix("shelf="))
+        .filter(|shelf| !shelf.is_empty())
+        .map(|shelf| shelf.to_lowercase());
+    Some(GoodreadsInput { user_id, shelf })
+}
+
+// "12345678-jane" -> "12345678"
+fn leading_id(segment: &str) -> Option<String> {
+    let digits = segment
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    let rest = &segment[digits.len()..];
+    if digits.is_empty() || !(rest.is_empty() || rest.starts_with('-')) {
+        return None;
+    }
+    Some(digits)
+}
+
+/// Reads the books on `shelf` out of a Goodreads library export ("Export Library" in the
+/// Goodreads settings), which works for private profiles too. Uses the Title, Author,
+/// Additional Authors, ISBN13, Exclusive Shelf, Date Added, Average Rating and Original
+/// Publication Year (or Year Published) columns.
+/// Returns `None` when the file doesn't look like a Goodreads export.
+pub fn parse_export_csv(csv: &str, shelf: &str) -> Option<Vec<GoodreadsBook>> {
+    let mut records = csv_records(csv).into_iter();
+    let header = records.next()?;
+    let column = |name: &str| header.iter().position(|field| field.trim() == name);
+    let (title_column, author_column, shelf_column) = (
+        column("Title")?,
+        column("Author")?,
+        column("Exclusive Shelf")?,
+    );
+    let additional_authors_column = column("Additional Authors");
+    let isbn_column = column("ISBN13");
+    let date_added_column = column("Date Added");
+    let avg_rating_column = column("Average Rating");
+    let publication_year_column = column("Original Publication Year");
+    let year_published_column = column("Year Published");
+
+    let books = records
+        .filter(|record| record.get(shelf_column).map(|s| s.trim()) == Some(shelf))
+        .filter_map(|record| {
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| record.get(index))
+                    .map(|value| value.trim())
+                    .filter(|value| !value.is_empty())
+            };
+            let (title, series) = split_title_series(field(Some(title_column))?);
+            let authors = field(Some(author_column))
+                .into_iter()
+                .chain(
+                    field(additional_authors_column)
+                        .into_iter()
+                        .flat_map(|authors| authors.split(',')),
+                )
+                .map(|author| author.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|author| !author.is_empty())
+                .collect::<Vec<_>>();
+            // ISBNs are written as ="9780765326355" so spreadsheets keep the leading digits
+            let isbn = field(isbn_column)
+                .map(|isbn| {
+                    isbn.chars()
+                        .filter(|c| c.is_ascii_digit())
+                        .collect::<String>()
+                })
+                .filter(|isbn| isbn.len() == 13);
+            Some(GoodreadsBook {
+                // the export has no cover images
+                cover: String::new(),
+                title,
+                author: authors.first().cloned().unwrap_or_default(),
+                authors,
+                series,
+                date_added: field(date_added_column)
+                    .and_then(|date| NaiveDate::parse_from_str(date, "%Y/%m/%d").ok()),
+                avg_rating: field(avg_rating_column)
+                    .and_then(|rating| rating.parse::<f32>().ok())
+                    .filter(|rating| *rating > 0.0),
+                isbn,
+                // the original year is blank for some books, the edition's year is better than none
+                publication_year: field(publication_year_column)
+                    .or(field(year_published_column))
+                    .and_then(|year| year.parse::<i32>().ok()),
+            })
+        })
+        .collect();
+    Some(books)
+}
+
+// the export puts the series in the title, e.g. "The Final Empire (Mistborn, #1)"
+fn split_title_series(title: &str) -> (String, Option<String>) {
+    if let Some((main, series)) = title
+        .strip_suffix(')')
+        .and_then(|title| title.rsplit_once('('))
+    {
+        if series.contains('#') && !main.trim().is_empty() {
+            return (main.trim().to_string(), Some(series.trim().to_string()));
+        }
+    }
+    (title.to_string(), None)
+}
+
+// splits CSV text into records, handling quoted fields with commas, quotes and line breaks
+fn csv_records(csv: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Turns pasted text, one title or ISBN per line, into books to look up. An ISBN line
+/// ("978-0-7653-1178-8", "0765311781") becomes a book titled by its ISBN-13, so it is only
+/// ever matched by ISBN; anything else is a title with no author.
+pub fn parse_pasted_books(text: &str) -> Vec<GoodreadsBook> {
+    let mut books = Vec::<GoodreadsBook>::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let isbn = isbn13(line);
+        let title = isbn.clone().unwrap_or_else(|| line.to_string());
+        if books.iter().any(|book| book.title == title) {
+            continue;
+        }
+        books.push(GoodreadsBook {
+            cover: String::new(),
+            title,
+            author: String::new(),
+            authors: Vec::new(),
+            series: None,
+            date_added: None,
+            avg_rating: None,
+            isbn,
+            publication_year: None,
+        });
+    }
+    books
+}
+
+// an ISBN-13, or an ISBN-10 converted to one, ignoring hyphens and spaces
+fn isbn13(line: &str) -> Option<String> {
+    let compact = line
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    match compact.len() {
+        13 if is_digits(&compact) && (compact.starts_with("978") || compact.starts_with("979")) => {
+            Some(compact)
+        }
+        10 if is_digits(&compact[..9]) && (is_digits(&compact[9..]) || &compact[9..] == "X") => {
+            let stem = format!("978{}", &compact[..9]);
+            let sum = stem
+                .chars()
+                .filter_map(|c| c.to_digit(10))
+                .enumerate()
+                .map(|(index, digit)| if index % 2 == 0 { digit } else { digit * 3 })
+                .sum::<u32>();
+            Some(format!("{}{}", stem, (10 - sum % 10) % 10))
+        }
+        _ => None,
+    }
+}
+
+/// Asks Goodreads for a bigger cover than the thumbnail the shelf page links, by swapping the
+/// size token before the extension, e.g. ".../3744438._SX50_.jpg" -> ".../3744438._SX98_.jpg"
+/// (and "._SY75_", "._SX50_SY75_" alike). URLs without a size token are returned unchanged.
+pub fn larger_cover_url(url: &str) -> String {
+    let Some(extension_at) = url.rfind('.') else {
+        return url.to_string();
+    };
+    let Some(token_at) = url[..extension_at].rfind("._") else {
+        return url.to_string();
+    };
+    let token = &url[token_at + 2..extension_at];
+    // one or more "SX50_"-style parts, i.e. two capitals, digits and an underscore
+    let is_size_token = !token.is_empty()
+        && token.split_terminator('_').all(|part| {
+            part.len() > 2
+                && part[..2].chars().all(|c| c.is_ascii_uppercase())
+                && part[2..].chars().all(|c| c.is_ascii_digit())
+        })
+        && token.ends_with('_');
+    if !is_size_token {
+        return url.to_string();
+    }
+    format!("{}._SX98_{}", &url[..token_at], &url[extension_at..])
+}
+
+/// How a second person's shelf is combined with the first's, for finding shared reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShelfCombination {
+    // only books on both shelves
+    Both,
+    // books on either shelf
+    Either,
+}
+
+/// Combines two people's shelves, comparing books by normalized title and author so the
+/// same book added from different editions still lines up. The first shelf's order (and its
+/// copy of a shared book) is kept, with `Either` adding the second shelf's other books after.
+pub fn combine_shelves(
+    first: Vec<GoodreadsBook>,
+    second: Vec<GoodreadsBook>,
+    combination: ShelfCombination,
+) -> Vec<GoodreadsBook> {
+    let key = |book: &GoodreadsBook| (normalize_title(&book.title), normalize_author(&book.author));
+    match combination {
+        ShelfCombination::Both => {
+            let second_keys = second.iter().map(key).collect::<HashSet<_>>();
+            first
+                .into_iter()
+                .filter(|book| second_keys.contains(&key(book)))
+                .collect()
+        }
+        ShelfCombination::Either => {
+            let mut seen = first.iter().map(key).collect::<HashSet<_>>();
+            let mut books = first;
+            books.extend(second.into_iter().filter(|book| seen.insert(key(book))));
+            books
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(user_id: &str, shelf: Option<&str>) -> Option<GoodreadsInput> {
+        Some(GoodreadsInput {
+            user_id: user_id.to_string(),
+            shelf: shelf.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn reads_bare_ids() {
+        assert_eq!(parse_user_input("12345678"), input("12345678", None));
+        assert_eq!(parse_user_input(" 12345678-jane "), input("12345678", None));
+    }
+
+    #[test]
+    fn reads_profile_and_shelf_links() {
+        assert_eq!(
+            parse_user_input("https://www.goodreads.com/review/list/12345678-jane?shelf=to-read"),
+            input("12345678", Some("to-read"))
+        );
+        assert_eq!(
+            parse_user_input(
+                "https://www.goodreads.com/review/list/12345678?sort=date_added&shelf=Currently-Reading"
+            ),
+            input("12345678", Some("currently-reading"))
+        );
+        assert_eq!(
+            parse_user_input("https://www.goodreads.com/user/show/12345678-jane"),
+            input("12345678", None)
+        );
+        assert_eq!(
+            parse_user_input("goodreads.com/user/show/12345678-jane#reviews"),
+            input("12345678", None)
+        );
+    }
+
+    #[test]
+    fn reads_app_share_links() {
+        assert_eq!(
+            parse_user_input(
+                "https://goodreads.com/user/show/12345678?utm_medium=api&utm_source=app_share"
+            ),
+            input("12345678", None)
+        );
+        assert_eq!(
+            parse_user_input(
+                "https://www.goodreads.com/review/list/12345678?shelf=to-read&utm_source=app_share#top"
+            ),
+            input("12345678", Some("to-read"))
+        );
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_user() {
+        assert_eq!(parse_user_input(""), None);
+        assert_eq!(parse_user_input("jane"), None);
+        assert_eq!(parse_user_input("12345abc"), None);
+        assert_eq!(
+            parse_user_input("https://www.goodreads.com/book/show/9361589-the-night-circus"),
+            None
+        );
+    }
+
+    #[test]
+    fn asks_for_larger_covers() {
+        for (thumbnail, larger) in [
+            (
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1387124618i/9361589._SY75_.jpg",
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1387124618i/9361589._SX98_.jpg",
+            ),
+            (
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1400322851i/22055262._SX50_SY75_.jpg",
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1400322851i/22055262._SX98_.jpg",
+            ),
+            (
+                "https://images.gr-assets.com/books/1327881361s/3744438._SX50_.jpg",
+                "https://images.gr-assets.com/books/1327881361s/3744438._SX98_.jpg",
+            ),
+            (
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1545494980i/40916679._SY180_.png",
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1545494980i/40916679._SX98_.png",
+            ),
+        ] {
+            assert_eq!(larger_cover_url(thumbnail), larger);
+        }
+    }
+
+    #[test]
+    fn leaves_covers_without_a_size_token_alone() {
+        for url in [
+            "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1387124618i/9361589.jpg",
+            "https://images.gr-assets.com/books/1327881361s/3744438._custom_.jpg",
+            "https://images.gr-assets.com/books/1327881361s/3744438",
+            "",
+        ] {
+            assert_eq!(larger_cover_url(url), url);
+        }
+    }
+}
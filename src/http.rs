@@ -0,0 +1,102 @@
+use reqwest::{Client, Response, StatusCode};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Goodreads tends to serve a bot-challenge page to non-browser user agents
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+    (KHTML, like Gecko) Chrome/129.0.0.0 Safari/537.36";
+
+/// Attempts `get_with_retry` makes before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+/// Backoff before the first retry, doubled for every retry after that.
+const BASE_BACKOFF: Duration = Duration::from_millis(250);
+/// Longest `Retry-After` we're willing to sit through before retrying a 429.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// The one HTTP client every server function shares, so connections and TLS sessions
+/// to Goodreads and Overdrive are pooled instead of re-negotiated per request.
+pub fn http_client() -> &'static Client {
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            // drop idle connections before the upstream servers do
+            .pool_idle_timeout(Duration::from_secs(60))
+            .pool_max_idle_per_host(32)
+            .build()
+            .expect("failed to build the shared reqwest client")
+    })
+}
+
+/// GETs `url`, retrying network errors, 5xx and 429 responses with exponential backoff and
+/// jitter, waiting at least as long as a 429's `Retry-After` asks. Other 4xx responses are
+/// returned right away, as is the last attempt's result.
+pub async fn get_with_retry(client: &Client, url: &str) -> reqwest::Result<Response> {
+    let mut attempt = 1;
+    loop {
+        let result = client.get(url).send().await;
+        let retryable = match &result {
+            Ok(response) => is_retryable_status(response.status()),
+            Err(err) => err.is_timeout() || err.is_connect() || err.is_request(),
+        };
+        if !retryable || attempt >= MAX_ATTEMPTS {
+            if retryable || result.is_err() {
+                let host = reqwest::Url::parse(url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_default();
+                metrics::counter!("libbyreads_upstream_errors_total", "host" => host).increment(1);
+            }
+            return result;
+        }
+        let retry_after = result.as_ref().ok().and_then(retry_after);
+        let backoff = backoff(attempt).max(retry_after.unwrap_or_default());
+        warn!(
+            attempt = attempt,
+            url = url,
+            status = result
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16()),
+            error = result.as_ref().err().map(|err| err.to_string()),
+            backoff_ms = backoff.as_millis() as u64,
+            "Retrying upstream request."
+        );
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+// the delay a 429 asks for, in seconds. the HTTP-date form is rare enough to ignore
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let seconds = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// 250ms, 500ms, ... plus up to 50% jitter so concurrent retries don't land together
+fn backoff(attempt: u32) -> Duration {
+    let base = BASE_BACKOFF * 2u32.pow(attempt - 1);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or_default();
+    base + base.mul_f64(f64::from(nanos % 1000) / 2000.0)
+}
@@ -1,7 +1,32 @@
+#[cfg(feature = "ssr")]
+pub mod api;
 pub mod app;
+#[cfg(feature = "ssr")]
+pub mod cache;
+#[cfg(feature = "ssr")]
+pub mod config;
 pub mod error_template;
+pub mod export;
 #[cfg(feature = "ssr")]
 pub mod fileserv;
+pub mod goodreads;
+#[cfg(feature = "ssr")]
+pub mod http;
+#[cfg(feature = "ssr")]
+pub mod lookup;
+pub mod matching;
+#[cfg(feature = "ssr")]
+pub mod notify;
+#[cfg(feature = "ssr")]
+pub mod refresh;
+#[cfg(feature = "ssr")]
+pub mod smtp;
+#[cfg(feature = "ssr")]
+pub mod status_store;
+pub mod storage;
+#[cfg(feature = "ssr")]
+pub mod telemetry;
+pub mod types;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,2042 @@
+use crate::cache::TtlCache;
+use crate::config::config;
+use crate::goodreads;
+use crate::http::{get_with_retry, http_client};
+pub use crate::matching::MatchStrictness;
+use crate::matching::{
+    authors_match, normalize_author, normalize_title, search_query, titles_match,
+};
+use chrono::{NaiveDate, Utc};
+use futures::{stream::FuturesUnordered, StreamExt};
+use reqwest::Client;
+use scraper::{Html, Selector};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tracing::{info, warn};
+use urlencoding::encode;
+
+pub use crate::error_template::{
+    AvailabilityError, GoodreadsError, HooplaError, LibraryError, StorygraphError,
+};
+use crate::types::COVER_PLACEHOLDER;
+pub use crate::types::{
+    BookAvailability, BookFormat, FormatAvailability, GoodreadsBook, HooplaTitle, LibbyBook,
+    LibbyLibraryBook, Library, LibraryBranch, SearchLibrary, DEFAULT_SHELF,
+};
+
+/// Counts each format checked at each library, by whether a matching title was found.
+const LIBRARY_RESULTS_METRIC: &str = "libbyreads_library_results_total";
+
+/// Results per Overdrive search page.
+const OVERDRIVE_PER_PAGE: usize = 24;
+
+/// Books per Goodreads shelf page. 100 is the largest page size Goodreads honors.
+const GOODREADS_PER_PAGE: u32 = 100;
+
+/// Every book on the given shelves of a Goodreads user, each book only once. No shelves means
+/// the to-read shelf.
+pub async fn goodreads_books(
+    user_id: &str,
+    shelves: &[String],
+) -> Result<Vec<GoodreadsBook>, GoodreadsError> {
+    let mut shelves = shelves
+        .iter()
+        .map(|shelf| shelf.trim().to_string())
+        .filter(|shelf| !shelf.is_empty())
+        .collect::<Vec<_>>();
+    if shelves.is_empty() {
+        shelves.push(DEFAULT_SHELF.to_string());
+    }
+
+    // one permit pool shared by every shelf, so asking for more shelves doesn't mean more
+    // simultaneous connections to Goodreads
+    let page_permits = Arc::new(tokio::sync::Semaphore::new(
+        config().goodreads_max_concurrent_pages,
+    ));
+    let mut in_flight = shelves
+        .iter()
+        .map(|shelf| fetch_goodreads_shelf(user_id, shelf, Arc::clone(&page_permits)))
+        .collect::<FuturesUnordered<_>>();
+    let mut shelf_books = Vec::new();
+    while let Some(result) = in_flight.next().await {
+        shelf_books.push(result?);
+    }
+
+    // a book can sit on several shelves, or twice on one after being re-added, only keep the
+    // first. compared normalized, so "Dune (Dune, #1)" and "Dune" are the same book
+    let mut seen = std::collections::HashSet::new();
+    let fetched_books = shelf_books.into_iter().flatten().collect::<Vec<_>>();
+    let fetched_count = fetched_books.len();
+    let books = fetched_books
+        .into_iter()
+        .filter(|book| seen.insert((normalize_title(&book.title), normalize_author(&book.author))))
+        .collect::<Vec<_>>();
+    info!(
+        user_id = user_id,
+        shelves = ?shelves,
+        total_books = books.len(),
+        duplicates_removed = fetched_count - books.len(),
+        "Finished fetching all Goodreads shelves."
+    );
+    Ok(books)
+}
+
+/// Fetches every page of a single Goodreads shelf.
+async fn fetch_goodreads_shelf(
+    user_id: &str,
+    shelf: &str,
+    page_permits: Arc<tokio::sync::Semaphore>,
+) -> Result<Vec<GoodreadsBook>, GoodreadsError> {
+    let start = Instant::now();
+
+    let url = goodreads_shelf_url(user_id, shelf);
+    info!(
+        user_id = user_id,
+        shelf = shelf,
+        url = url,
+        "Fetching initial page."
+    );
+
+    // Parse the HTML document
+    let (last_page, first_page_books) = {
+        let client = http_client();
+        // the first page counts against the limit too, or many shelves would still burst
+        let permit = page_permits.acquire().await.unwrap();
+        let response = get_with_retry(client, &url).await?.text().await?;
+        drop(permit);
+        // scraper's parsing is CPU-bound, so keep it off the async worker threads. the first
+        // page's books are read here too, so it doesn't have to be fetched a second time
+        let first_page = tokio::task::spawn_blocking(move || {
+            parse_goodreads_page_count(&response)
+                .map(|page_count| (page_count, parse_goodreads_books(&response)))
+        })
+        .await?;
+        info!(user_id = user_id, "Parsed html successfully.");
+        match first_page {
+            Err(GoodreadsError::Blocked) => {
+                info!(
+                    user_id = user_id,
+                    shelf = shelf,
+                    url = url,
+                    "Goodreads response had no shelf table, assuming we were blocked."
+                );
+                return Err(GoodreadsError::Blocked);
+            }
+            result => result?,
+        }
+    };
+
+    let initial_page_duration = start.elapsed();
+    info!(
+        user_id = user_id,
+        total_pages = last_page,
+        duration_s = initial_page_duration.as_secs_f32(),
+        "Parsed number of pages from initial page."
+    );
+    let pages = fetch_pages(2..=last_page, page_permits, |page_number| {
+        let client = http_client();
+        let page_url = format!("{}&page={}", url, page_number);
+        info!(
+            user_id = user_id,
+            url = page_url,
+            "Fetching Goodreads books."
+        );
+        async move {
+            let text = get_with_retry(client, &page_url)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            // `Html` isn't Send, so it's built and dropped entirely inside the closure
+            let page_books =
+                tokio::task::spawn_blocking(move || parse_goodreads_books(&text)).await?;
+            Ok(page_books)
+        }
+    })
+    .await;
+
+    // A page that failed only costs its own books
+    let mut books = first_page_books;
+    let mut failed_pages = Vec::new();
+    for (page_number, result) in pages {
+        match result {
+            Ok(page_books) => books.extend(page_books),
+            Err(err) => {
+                warn!(
+                    user_id = user_id,
+                    shelf = shelf,
+                    page = page_number,
+                    error = %err,
+                    "Failed to fetch Goodreads page, skipping it."
+                );
+                failed_pages.push(page_number);
+            }
+        }
+    }
+
+    let duration = start.elapsed();
+    info!(
+        user_id = user_id,
+        shelf = shelf,
+        initial_page_load_time=?initial_page_duration,
+        all_pages_load_time=?duration,
+        total_pages=last_page,
+        failed_pages=?failed_pages,
+        total_books=books.len(),
+        "Finished fetching all Goodreads pages."
+    );
+    Ok(books)
+}
+
+// URL of the user's shelf
+// print=true here gives us a simpler webpage to parse
+// order=d sorts by descending
+// sort=date_added sorts by the order the books were added
+// per_page only accepts the values offered in the shelf's "per page" dropdown (up to 100),
+// anything else (e.g. 500) silently falls back to 20
+// custom shelf names can hold "&", "#" or spaces, which would break the query unencoded
+fn goodreads_shelf_url(user_id: &str, shelf: &str) -> String {
+    format!(
+        "https://goodreads.com/review/list/{}?print=true&shelf={}&per_page={}",
+        encode(user_id),
+        encode(shelf),
+        GOODREADS_PER_PAGE
+    )
+}
+
+// Runs `fetch_page` for each of `page_numbers` as their own tasks, but only lets as many of
+// them run at once as there are permits. Results come back in page order.
+async fn fetch_pages<F, Fut, T>(
+    page_numbers: std::ops::RangeInclusive<u32>,
+    page_permits: Arc<tokio::sync::Semaphore>,
+    fetch_page: F,
+) -> Vec<(u32, Result<T, GoodreadsError>)>
+where
+    F: Fn(u32) -> Fut,
+    Fut: Future<Output = Result<T, GoodreadsError>> + Send + 'static,
+    T: Send + 'static,
+{
+    let tasks = page_numbers
+        .map(|page_number| {
+            let page_permits = Arc::clone(&page_permits);
+            let page = fetch_page(page_number);
+            let task = tokio::task::spawn(async move {
+                // held until this page is done, then released for the next waiting page
+                let _permit = page_permits.acquire_owned().await.unwrap();
+                page.await
+            });
+            (page_number, task)
+        })
+        .collect::<Vec<_>>();
+    let mut pages = Vec::with_capacity(tasks.len());
+    for (page_number, task) in tasks {
+        let result = task
+            .await
+            .map_err(GoodreadsError::from)
+            .and_then(|result| result);
+        pages.push((page_number, result));
+    }
+    pages
+}
+
+// Checks the first page of a shelf for a private profile or a bot-challenge page,
+// then reads how many pages the shelf has.
+fn parse_goodreads_page_count(html: &str) -> Result<u32, GoodreadsError> {
+    let document = Html::parse_document(html);
+    // check for the `id=privateProfile` div, which indicates we won't be able to see any books
+    let private_profile_selector = Selector::parse("#privateProfile").unwrap();
+    if document.select(&private_profile_selector).next().is_some() {
+        return Err(GoodreadsError::PrivateProfile);
+    }
+    // a real shelf page always has the books table (even when the shelf is empty), so without
+    // it or any book rows we were most likely handed a bot-challenge page instead
+    let books_table_selector = Selector::parse("table#books, tr.bookalike.review").unwrap();
+    if document.select(&books_table_selector).next().is_none() {
+        return Err(GoodreadsError::Blocked);
+    }
+    // get the total number of pages. On long shelves Goodreads elides the middle page links
+    // ("1 2 3 … 17 18 next »"), so besides the link texts we also read the page each link
+    // points at, and the shelf's total book count, e.g. "Want to Read (1,942)"
+    let pagination_selector = Selector::parse("#reviewPagination a").unwrap();
+    let linked_pages = document.select(&pagination_selector).filter_map(|element| {
+        let from_text = element
+            .text()
+            .collect::<String>()
+            .trim()
+            .parse::<u32>()
+            .ok();
+        let from_href = element.value().attr("href").and_then(page_param);
+        from_text.max(from_href)
+    });
+    let selected_shelf_selector = Selector::parse("a.selectedShelf").unwrap();
+    let counted_pages = document
+        .select(&selected_shelf_selector)
+        .next()
+        .and_then(|element| shelf_book_count(&element.text().collect::<String>()))
+        .map(|count| count.div_ceil(GOODREADS_PER_PAGE));
+
+    // If there are no pagination links, there is only one page
+    Ok(linked_pages.chain(counted_pages).max().unwrap_or(1).max(1))
+}
+
+// the `page` query param of a pagination link, e.g. "/review/list/123?page=18&shelf=to-read"
+fn page_param(href: &str) -> Option<u32> {
+    let (_, query) = href.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("page="))
+        .and_then(|page| page.parse::<u32>().ok())
+}
+
+// the count in a shelf link's "Want to Read (1,942)" label
+fn shelf_book_count(label: &str) -> Option<u32> {
+    let (_, count) = label.rsplit_once('(')?;
+    count
+        .trim_end()
+        .strip_suffix(')')?
+        .replace(',', "")
+        .trim()
+        .parse::<u32>()
+        .ok()
+}
+
+// Reads every book row of a single shelf page.
+fn parse_goodreads_books(html: &str) -> Vec<GoodreadsBook> {
+    let document = Html::parse_document(html);
+
+    // i just looked at the HTML directly to determine these selectors
+    let book_rows_selector = Selector::parse("tr.bookalike.review").unwrap();
+    let cover_selector = Selector::parse("td.field.cover img").unwrap();
+    let title_selector = Selector::parse("td.field.title a").unwrap();
+    let series_selector = Selector::parse("span.darkGreyText").unwrap();
+    let author_selector = Selector::parse("td.field.author a").unwrap();
+    let date_added_selector = Selector::parse("td.field.date_added span").unwrap();
+    let avg_rating_selector = Selector::parse("td.field.avg_rating .value").unwrap();
+    let isbn_selector = Selector::parse("td.field.isbn13 .value").unwrap();
+    let date_pub_selector = Selector::parse("td.field.date_pub .value").unwrap();
+
+    let mut books = Vec::new();
+    // Loop through each book row
+    for book_row in document.select(&book_rows_selector) {
+        // Get cover image
+        // Goodreads links a generic "nophoto" image for books without a cover of their own
+        let cover = book_row
+            .select(&cover_selector)
+            .next()
+            .and_then(|element| element.value().attr("src"))
+            .filter(|src| !src.is_empty() && !src.contains("/nophoto/"))
+            .map(goodreads::larger_cover_url)
+            .unwrap_or_else(|| COVER_PLACEHOLDER.to_string());
+
+        // Get title. a row without one isn't a book we can look for, so it's skipped
+        let Some(title_element) = book_row.select(&title_selector).next() else {
+            warn!("Skipping a Goodreads shelf row without a title.");
+            continue;
+        };
+        // Remove the span with the class darkGreyText, which Goodreads sometimes adds
+        // e.g. A Darker Shade of Magic <span class="darkGreyText">(Shades of Magic, #1)</span>
+        // should become A Darker Shade of Magic
+        let title = title_element
+            .children() // Get the child nodes of the <a> tag
+            .filter(|node| node.value().is_text()) // Filter to get only the text nodes (ignoring <span>)
+            .map(|node| node.value().as_text().unwrap().trim()) // Extract and trim the text
+            .filter(|text| !text.is_empty()) // e.g. the line break after the series <span>
+            .collect::<Vec<_>>() // Collect the text parts
+            .join(" "); // Join them into a single string
+
+        // and keep the series from that span on its own, without the parentheses
+        let series = title_element
+            .select(&series_selector)
+            .next()
+            .map(|element| {
+                element
+                    .text()
+                    .collect::<String>()
+                    .trim()
+                    .trim_start_matches('(')
+                    .trim_end_matches(')')
+                    .trim()
+                    .to_string()
+            })
+            .filter(|series| !series.is_empty());
+
+        // Get authors, a co-authored book links each of them
+        let authors = book_row
+            .select(&author_selector)
+            .map(|element| element.text().collect::<String>().trim().to_string())
+            .filter(|author| !author.is_empty())
+            .collect::<Vec<_>>();
+        let author = authors.first().cloned().unwrap_or_default();
+        // Get date added, e.g. <span title="March 3, 2021">Mar 03, 2021</span>
+        let date_added = book_row
+            .select(&date_added_selector)
+            .next()
+            .and_then(parse_goodreads_date);
+
+        // Get average rating. Books nobody has rated yet show "0.00"
+        let avg_rating = book_row
+            .select(&avg_rating_selector)
+            .next()
+            .and_then(|element| {
+                element
+                    .text()
+                    .collect::<String>()
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+            })
+            .filter(|rating| *rating > 0.0);
+
+        // Get ISBN13, which is blank for some editions (e.g. Kindle-only ones)
+        let isbn = book_row
+            .select(&isbn_selector)
+            .next()
+            .map(|element| {
+                element
+                    .text()
+                    .collect::<String>()
+                    .chars()
+                    .filter(|c| c.is_ascii_digit())
+                    .collect::<String>()
+            })
+            .filter(|isbn| isbn.len() == 13);
+
+        // Get the original publication year, from e.g. "Sep 29, 2015", "2015" or "unknown"
+        let publication_year = book_row
+            .select(&date_pub_selector)
+            .next()
+            .and_then(|element| {
+                element
+                    .text()
+                    .collect::<String>()
+                    .split(|c: char| !c.is_ascii_digit())
+                    .rfind(|part| part.len() == 4)
+                    .and_then(|year| year.parse::<i32>().ok())
+            });
+
+        // Create a book struct
+        let book = GoodreadsBook {
+            cover,
+            title,
+            author,
+            authors,
+            series,
+            date_added,
+            avg_rating,
+            isbn,
+            publication_year,
+        };
+        books.push(book);
+    }
+    books
+}
+
+// Goodreads shows "Mar 03, 2021" with the long form "March 3, 2021" in the title attribute
+fn parse_goodreads_date(element: scraper::ElementRef) -> Option<NaiveDate> {
+    let text = element.text().collect::<String>();
+    NaiveDate::parse_from_str(text.trim(), "%b %d, %Y")
+        .ok()
+        .or_else(|| {
+            let title = element.value().attr("title")?;
+            NaiveDate::parse_from_str(title.trim(), "%B %d, %Y").ok()
+        })
+}
+
+/// The names of a Goodreads user's shelves, with "all" first.
+pub async fn goodreads_shelves(user_id: &str) -> Result<Vec<String>, GoodreadsError> {
+    info!(user_id = user_id, "Fetching Goodreads shelves.");
+    let url = format!("https://goodreads.com/review/list/{}", encode(user_id));
+    let client = http_client();
+    let response = get_with_retry(client, &url)
+        .await
+        .map_err(GoodreadsError::from)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        info!(user_id = user_id, "Goodreads user not found.");
+        return Err(GoodreadsError::NotFound);
+    }
+    let response = response.text().await.map_err(GoodreadsError::from)?;
+    let document = Html::parse_document(&response);
+    if document
+        .select(&Selector::parse("#privateProfile").unwrap())
+        .next()
+        .is_some()
+    {
+        return Err(GoodreadsError::PrivateProfile);
+    }
+    let shelf_selector = Selector::parse(".userShelf a").unwrap();
+    // Create an empty vector to hold the shelves
+    let mut shelves = Vec::new();
+
+    // Iterate over each element that matches the selector
+    for element in document.select(&shelf_selector) {
+        // Get the href attribute
+        if let Some(href) = element.value().attr("href") {
+            // Split on "shelf=" and get the part after it
+            if let Some(shelf_name) = href.split("shelf=").nth(1) {
+                // Split on any query parameters (in case there are any)
+                let shelf_name_cleaned = shelf_name
+                    .split('&')
+                    .next()
+                    .unwrap_or(shelf_name)
+                    .to_lowercase();
+                // Push the cleaned shelf name to the vector
+                shelves.push(shelf_name_cleaned.to_string());
+            }
+        }
+    }
+    if !shelves.contains(&"all".to_string()) {
+        shelves.insert(0, "all".to_string());
+    }
+    info!(
+        shelves = ?shelves,
+        user_id = user_id,
+        "Finished fetching Goodreads shelves."
+    );
+    Ok(shelves)
+}
+
+/// Checks one book at every library, in every format asked for (the default formats if none),
+/// in any language unless `languages` (Overdrive language codes, e.g. "en") are given.
+/// A library that can't be checked is listed in `errored_libraries` instead of failing the book.
+/// An ISBN match always counts, `strictness` only decides title and author matches.
+/// A `pinned_title_id` is looked up directly wherever a library has it, in place of searching.
+/// `bypass_cache` searches Overdrive even if the same search ran recently, for re-checks and
+/// refreshes that need today's answer rather than one from hours ago.
+#[allow(clippy::too_many_arguments)]
+pub async fn libby_availability(
+    book: &GoodreadsBook,
+    libraries: &[Library],
+    formats: &[BookFormat],
+    languages: &[String],
+    exclude_preorders: bool,
+    strictness: MatchStrictness,
+    pinned_title_id: Option<&str>,
+    bypass_cache: bool,
+) -> Result<LibbyBook, AvailabilityError> {
+    if libraries.is_empty() {
+        return Err(AvailabilityError::NoLibraries);
+    }
+    let formats = if formats.is_empty() {
+        BookFormat::DEFAULT.to_vec()
+    } else {
+        formats.to_vec()
+    };
+    // they end up in the Overdrive url
+    let languages = languages
+        .iter()
+        .filter(|language| {
+            !language.is_empty() && language.chars().all(|c| c.is_ascii_alphabetic())
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let client = http_client();
+
+    // ask every library at once, remembering each one's position so the
+    // "first available, else first holdable" pick below stays in library order
+    let mut in_flight = libraries
+        .iter()
+        .enumerate()
+        .map(|(index, library)| {
+            let formats = &formats;
+            let languages = &languages;
+            async move {
+                let checked = async {
+                    // the pinned title answers for its own formats, the rest are still searched
+                    let mut found = match pinned_title_id {
+                        Some(title_id) => fetch_pinned(client, library, title_id, formats).await?,
+                        None => Vec::new(),
+                    };
+                    let missing_formats = formats
+                        .iter()
+                        .copied()
+                        .filter(|format| !found.iter().any(|found| found.format == *format))
+                        .collect::<Vec<_>>();
+                    if !missing_formats.is_empty() {
+                        found.extend(
+                            check_library(
+                                client,
+                                book,
+                                library,
+                                &missing_formats,
+                                languages,
+                                exclude_preorders,
+                                strictness,
+                                bypass_cache,
+                            )
+                            .await?,
+                        );
+                        found.sort_by_key(|libby_library_book| {
+                            formats
+                                .iter()
+                                .position(|format| *format == libby_library_book.format)
+                        });
+                    }
+                    Ok::<_, AvailabilityError>(found)
+                };
+                (index, checked.await)
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+    let mut indexed_books = Vec::with_capacity(libraries.len());
+    let mut errored_libraries = Vec::new();
+    while let Some((index, result)) = in_flight.next().await {
+        match result {
+            Ok(libby_library_books) => indexed_books.push((index, libby_library_books)),
+            Err(err) => {
+                let library = &libraries[index].search_library.system_name;
+                warn!(
+                    title = book.title,
+                    library = library,
+                    error = %err,
+                    "Could not check library."
+                );
+                errored_libraries.push(library.clone());
+            }
+        }
+    }
+    indexed_books.sort_by_key(|(index, _)| *index);
+    let libby_library_books = indexed_books
+        .into_iter()
+        .flat_map(|(_, libby_library_books)| libby_library_books)
+        .collect::<Vec<_>>();
+
+    let overall = pick_availability(libby_library_books.iter());
+    let for_format = |format: BookFormat| {
+        pick_availability(
+            libby_library_books
+                .iter()
+                .filter(|libby_library_book| libby_library_book.format == format),
+        )
+    };
+    let is_available = overall.as_ref().is_some_and(|overall| overall.is_available);
+    let is_holdable = overall.as_ref().is_some_and(|overall| overall.is_holdable);
+    let availability = if is_available {
+        BookAvailability::Available
+    } else if is_holdable {
+        BookAvailability::Holdable
+    } else if !errored_libraries.is_empty() {
+        BookAvailability::Unknown
+    } else {
+        BookAvailability::NotOwned
+    };
+    let libby_book = LibbyBook {
+        cover: book.cover.to_string(),
+        title: book.title.to_string(),
+        author: book.author.to_string(),
+        availability,
+        is_available,
+        is_holdable,
+        estimated_wait_days: overall
+            .as_ref()
+            .and_then(|overall| overall.estimated_wait_days),
+        available_copies: overall
+            .as_ref()
+            .and_then(|overall| overall.available_copies),
+        libby_search_url: overall
+            .map(|overall| overall.libby_search_url)
+            .unwrap_or_default(),
+        errored_libraries,
+        ebook: for_format(BookFormat::Ebook),
+        audiobook: for_format(BookFormat::Audiobook),
+        magazine: for_format(BookFormat::Magazine),
+        comic: for_format(BookFormat::Comic),
+        publisher: libby_library_books
+            .iter()
+            .find_map(|library_book| library_book.publisher.clone()),
+        publication_year: libby_library_books
+            .iter()
+            .find_map(|library_book| library_book.publication_year),
+        library_books: libby_library_books.clone(),
+        checked_at: Some(Utc::now()),
+        pinned_title_id: pinned_title_id.map(str::to_string),
+    };
+    Ok(libby_book)
+}
+
+/// Summarizes per-library results, pointing at the first library that has the book available,
+/// or failing that the holdable one with the shortest wait (the first of those on a tie), or
+/// failing that the first library. None if there are no results to summarize.
+///
+/// Every library is looked at before picking, so the answer doesn't depend on which order the
+/// libraries came back in beyond breaking ties.
+fn pick_availability<'a>(
+    libby_library_books: impl Iterator<Item = &'a LibbyLibraryBook>,
+) -> Option<FormatAvailability> {
+    let libby_library_books = libby_library_books.collect::<Vec<_>>();
+    let first = libby_library_books.first()?;
+    let first_available = libby_library_books
+        .iter()
+        .find(|libby_library_book| libby_library_book.is_available);
+    // a known wait beats an unknown one, and a shorter wait beats a longer one.
+    // min_by_key keeps the first of equal keys
+    let best_holdable = libby_library_books
+        .iter()
+        .filter(|libby_library_book| libby_library_book.is_holdable)
+        .min_by_key(|libby_library_book| {
+            (
+                libby_library_book.estimated_wait_days.is_none(),
+                libby_library_book.estimated_wait_days,
+            )
+        });
+    let is_available = first_available.is_some();
+    let is_holdable = best_holdable.is_some();
+    let (libby_search_url, estimated_wait_days) = match (first_available, best_holdable) {
+        (Some(available), _) => (&available.libby_search_url, None),
+        (None, Some(holdable)) => (&holdable.libby_search_url, holdable.estimated_wait_days),
+        (None, None) => (&first.libby_search_url, None),
+    };
+    let available_copies = libby_library_books
+        .iter()
+        .filter(|libby_library_book| libby_library_book.is_available)
+        .filter_map(|libby_library_book| libby_library_book.available_copies)
+        .max();
+    Some(FormatAvailability {
+        is_available,
+        is_holdable,
+        libby_search_url: libby_search_url.to_string(),
+        estimated_wait_days,
+        available_copies,
+    })
+}
+
+/// Results of `check_library`, keyed by library system id and a normalized form of the book and
+/// search options, so re-running a shelf doesn't search Overdrive again for every book.
+fn library_results_cache() -> &'static TtlCache<(String, String), Vec<LibbyLibraryBook>> {
+    static CACHE: OnceLock<TtlCache<(String, String), Vec<LibbyLibraryBook>>> = OnceLock::new();
+    CACHE.get_or_init(|| TtlCache::new(config().overdrive_cache_ttl))
+}
+
+/// Looks the book up in a single library's Overdrive catalog, with one result per format.
+/// Answers from the cache when the same lookup ran recently, unless `bypass_cache` is set.
+/// Either way a fresh search replaces what was cached.
+#[allow(clippy::too_many_arguments)]
+async fn check_library(
+    client: &Client,
+    book: &GoodreadsBook,
+    library: &Library,
+    formats: &[BookFormat],
+    languages: &[String],
+    exclude_preorders: bool,
+    strictness: MatchStrictness,
+    bypass_cache: bool,
+) -> Result<Vec<LibbyLibraryBook>, AvailabilityError> {
+    // titles that only normalize the same can still come out differently under Exact, so they
+    // can't share an entry there
+    let cache_title = match strictness {
+        MatchStrictness::Exact => book.title.clone(),
+        _ => normalize_title(&book.title),
+    };
+    let cache_key = (
+        library.system_id.clone(),
+        format!(
+            "{}|{}|{}|{:?}|{}|{}|{:?}",
+            cache_title,
+            book.author.to_lowercase(),
+            book.isbn.as_deref().unwrap_or_default(),
+            formats,
+            languages.join(","),
+            exclude_preorders,
+            strictness,
+        ),
+    );
+    if let Some(cached) = library_results_cache()
+        .get(&cache_key)
+        .filter(|_| !bypass_cache)
+    {
+        info!(
+            title = book.title,
+            library = library.search_library.system_name,
+            "Using cached Overdrive results.",
+        );
+        return Ok(cached);
+    }
+    let found = search_library(
+        client,
+        book,
+        library,
+        formats,
+        languages,
+        exclude_preorders,
+        strictness,
+    )
+    .await?;
+    library_results_cache().insert(cache_key, found.clone());
+    Ok(found)
+}
+
+async fn search_library(
+    client: &Client,
+    book: &GoodreadsBook,
+    library: &Library,
+    formats: &[BookFormat],
+    languages: &[String],
+    exclude_preorders: bool,
+    strictness: MatchStrictness,
+) -> Result<Vec<LibbyLibraryBook>, AvailabilityError> {
+    let query = search_query(&book.title, &book.author);
+    let url_safe_query = encode(&query);
+    let libby_search_url: String = format!(
+        "{}/search/query-{}/page-1",
+        library.libby_base_url, url_safe_query
+    );
+    info!(
+        title = book.title,
+        author = book.author,
+        isbn = book.isbn,
+        library = library.search_library.system_name,
+        libby_search_url = libby_search_url,
+        "Searching for book.",
+    );
+
+    // an ISBN pins down the exact edition, so any hit for it is our book
+    let mut found = Vec::<LibbyLibraryBook>::new();
+    if let Some(isbn) = &book.isbn {
+        let items = search_overdrive(
+            client,
+            library,
+            isbn,
+            formats,
+            languages,
+            exclude_preorders,
+            1,
+        )
+        .await?;
+        for format in formats.iter().copied() {
+            if let Some(libby_library_book) = items
+                .iter()
+                .filter(|item| !(exclude_preorders && is_preorder(item)))
+                .flat_map(|item| {
+                    libby_library_books_from_item(item, library, &libby_search_url, formats)
+                })
+                .find(|libby_library_book| libby_library_book.format == format)
+            {
+                info!(
+                    isbn = isbn,
+                    format = ?format,
+                    library = library.search_library.system_name,
+                    "Matched book by ISBN.",
+                );
+                metrics::counter!(LIBRARY_RESULTS_METRIC, "result" => "matched").increment(1);
+                found.push(libby_library_book);
+            }
+        }
+    }
+
+    // otherwise check the title + author results until we find a title that matches the book title
+    let missing_formats = formats
+        .iter()
+        .copied()
+        .filter(|format| !found.iter().any(|found| found.format == *format))
+        .collect::<Vec<_>>();
+    if !missing_formats.is_empty() {
+        // common titles can push the right edition past the first page, so keep paging until
+        // every missing format is matched, the results run out, or we hit the page cap
+        let mut matches = Vec::<LibbyLibraryBook>::new();
+        // a pasted ISBN is "titled" by the ISBN itself, so there is no title to look for
+        let isbn_only = book.isbn.as_deref() == Some(book.title.as_str());
+        let max_pages = if isbn_only {
+            0
+        } else {
+            config().overdrive_max_pages
+        };
+        for page in 1..=max_pages {
+            let items = search_overdrive(
+                client,
+                library,
+                &query,
+                formats,
+                languages,
+                exclude_preorders,
+                page,
+            )
+            .await?;
+            let page_matches = items
+                .iter()
+                .filter(|item| !(exclude_preorders && is_preorder(item)))
+                .flat_map(|item| {
+                    libby_library_books_from_item(item, library, &libby_search_url, formats)
+                })
+                .filter(|libby_library_book| {
+                    item_matches_book(libby_library_book, book, strictness)
+                })
+                .collect::<Vec<_>>();
+            if page > 1 && !page_matches.is_empty() {
+                info!(
+                    title = book.title,
+                    library = library.search_library.system_name,
+                    page = page,
+                    "Matched book beyond the first page of results.",
+                );
+            }
+            matches.extend(page_matches);
+            let all_matched = missing_formats
+                .iter()
+                .all(|format| matches.iter().any(|matched| matched.format == *format));
+            if all_matched || items.len() < OVERDRIVE_PER_PAGE {
+                break;
+            }
+        }
+        for format in missing_formats {
+            match matches.iter().find(|matched| matched.format == format) {
+                Some(libby_library_book) => {
+                    metrics::counter!(LIBRARY_RESULTS_METRIC, "result" => "matched").increment(1);
+                    found.push(libby_library_book.clone());
+                }
+                None => {
+                    metrics::counter!(LIBRARY_RESULTS_METRIC, "result" => "not_found").increment(1);
+                    info!(
+                        goodreads_title = book.title,
+                        goodreads_author = book.author,
+                        format = ?format,
+                        library = library.search_library.system_name,
+                        "Did not find book in libby.",
+                    );
+                    found.push(LibbyLibraryBook {
+                        cover: "".to_string(),
+                        title: book.title.to_string(),
+                        author: book.author.to_string(),
+                        library: library.search_library.system_name.clone(),
+                        format,
+                        is_available: false,
+                        is_holdable: false,
+                        libby_search_url: libby_search_url.to_string(),
+                        title_id: None,
+                        estimated_wait_days: None,
+                        holds_count: None,
+                        owned_copies: None,
+                        available_copies: None,
+                        narrator: None,
+                        duration: None,
+                        publisher: None,
+                        publication_year: None,
+                    });
+                }
+            }
+        }
+    }
+
+    // keep formats in a stable order, whichever search found them
+    found.sort_by_key(|libby_library_book| {
+        formats
+            .iter()
+            .position(|format| *format == libby_library_book.format)
+    });
+    Ok(found)
+}
+
+/// The pinned Overdrive title at one library, as one result per searched format it counts as.
+/// Empty when the library doesn't have that title.
+async fn fetch_pinned(
+    client: &Client,
+    library: &Library,
+    title_id: &str,
+    formats: &[BookFormat],
+) -> Result<Vec<LibbyLibraryBook>, AvailabilityError> {
+    let url = format!(
+        "{}/media/{}?x-client-id=dewey",
+        library.overdrive_base_url,
+        encode(title_id)
+    );
+    let response = get_with_retry(client, &url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !response.status().is_success() {
+        return Err(AvailabilityError::Request(format!(
+            "Overdrive title lookup failed with status {}",
+            response.status()
+        )));
+    }
+    // a body that isn't JSON at all (e.g. a proxy's error page) says nothing about the title
+    let item: Value = serde_json::from_str(&response.text().await?).map_err(|err| {
+        warn!(
+            library = library.search_library.system_name,
+            title_id = title_id,
+            error = %err,
+            "Overdrive title lookup response wasn't JSON."
+        );
+        AvailabilityError::Malformed
+    })?;
+    let libby_url = format!("{}/media/{}", library.libby_base_url, title_id);
+    let found = libby_library_books_from_item(&item, library, &libby_url, formats);
+    info!(
+        title_id = title_id,
+        library = library.search_library.system_name,
+        found = found.len(),
+        "Looked up pinned title."
+    );
+    Ok(found)
+}
+
+/// Fetches a page (starting at 1) of Overdrive search results for `query` at `library`, in any
+/// language unless `languages` are given.
+async fn search_overdrive(
+    client: &Client,
+    library: &Library,
+    query: &str,
+    formats: &[BookFormat],
+    languages: &[String],
+    exclude_preorders: bool,
+    page: usize,
+) -> Result<Vec<Value>, AvailabilityError> {
+    // query every format at once, items say which format they are in `type.id`. comics share
+    // the ebook formats, so each Overdrive format is only asked for once
+    let mut overdrive_formats = Vec::new();
+    for overdrive_format in formats
+        .iter()
+        .flat_map(|format| format.overdrive_formats().split(','))
+    {
+        if exclude_preorders && overdrive_format.ends_with("-provisional") {
+            continue;
+        }
+        if !overdrive_formats.contains(&overdrive_format) {
+            overdrive_formats.push(overdrive_format);
+        }
+    }
+    let format_str = format!("format={}", overdrive_formats.join(","));
+    let mut overdrive_url = format!(
+        "{}/media?query={}&{}&perPage={}&page={}&truncateDescription=false&x-client-id=dewey",
+        library.overdrive_base_url,
+        encode(query),
+        format_str,
+        OVERDRIVE_PER_PAGE,
+        page,
+    );
+    if !languages.is_empty() {
+        overdrive_url.push_str(&format!("&language={}", languages.join(",")));
+    }
+
+    // Fetch the page content. a rate limit or server error that outlasted the retries says
+    // nothing about whether the library owns the book, so it is an error, not "no items"
+    let response = get_with_retry(client, &overdrive_url).await?;
+    if !response.status().is_success() {
+        warn!(
+            library = library.search_library.system_name,
+            query = query,
+            status = response.status().as_u16(),
+            "Overdrive search failed."
+        );
+        return Err(AvailabilityError::Request(format!(
+            "Overdrive search failed with status {}",
+            response.status()
+        )));
+    }
+    let response = response.text().await?;
+
+    // Parse the JSON document. Overdrive answers errors with an object that has no `items`,
+    // which we treat the same as no results at this library. A body that isn't JSON at all
+    // (e.g. a proxy's error page or a cut-off response) leaves the book unknown instead
+    let json: Value = serde_json::from_str(&response).map_err(|err| {
+        warn!(
+            library = library.search_library.system_name,
+            query = query,
+            error = %err,
+            "Overdrive search response wasn't JSON."
+        );
+        AvailabilityError::Malformed
+    })?;
+    match json.get("items").and_then(|items| items.as_array()) {
+        Some(items) => Ok(items.clone()),
+        None => {
+            info!(
+                library = library.search_library.system_name,
+                query = query,
+                "Overdrive response had no items."
+            );
+            Ok(Vec::new())
+        }
+    }
+}
+
+// a title that's announced but not out yet, which Overdrive still lists as holdable
+fn is_preorder(item: &Value) -> bool {
+    item.get("isPreReleaseTitle")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+fn item_matches_book(
+    libby_library_book: &LibbyLibraryBook,
+    book: &GoodreadsBook,
+    strictness: MatchStrictness,
+) -> bool {
+    // pasted titles come without an author, so the title has to do. so do magazines, whose
+    // "author" is an editor or publisher that rarely lines up with Goodreads
+    titles_match(&book.title, &libby_library_book.title, strictness)
+        && (book.authors.is_empty()
+            || libby_library_book.format == BookFormat::Magazine
+            || authors_match(&book.authors, &libby_library_book.author, strictness))
+}
+
+/// Reads an Overdrive media item as one result per searched format it counts as: a comic is
+/// both an ebook and a comic. Skips items without a title, books without an author, and
+/// formats we don't know.
+fn libby_library_books_from_item(
+    item: &Value,
+    library: &Library,
+    libby_search_url: &str,
+    formats: &[BookFormat],
+) -> Vec<LibbyLibraryBook> {
+    let Some(item_format) = item
+        .pointer("/type/id")
+        .and_then(|v| v.as_str())
+        .and_then(BookFormat::from_overdrive_type_id)
+    else {
+        return Vec::new();
+    };
+    let is_comic = item
+        .get("subjects")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|subject| subject.get("name").and_then(|v| v.as_str()))
+        .any(|subject| subject.contains("Comic") || subject.contains("Graphic Novel"));
+    let mut item_formats = vec![item_format];
+    if item_format == BookFormat::Ebook && is_comic {
+        item_formats.push(BookFormat::Comic);
+    }
+    item_formats
+        .into_iter()
+        .filter(|format| formats.contains(format))
+        .filter_map(|format| libby_library_book_from_item(item, library, libby_search_url, format))
+        .collect()
+}
+
+// one result for an item, in the given format
+fn libby_library_book_from_item(
+    item: &Value,
+    library: &Library,
+    libby_search_url: &str,
+    format: BookFormat,
+) -> Option<LibbyLibraryBook> {
+    let title = item
+        .get("title")
+        .and_then(|v| v.as_str())?
+        .replace("\n", "");
+    let author = match item.get("firstCreatorSortName").and_then(|v| v.as_str()) {
+        Some(author) => author,
+        // magazines are matched on title alone
+        None if format == BookFormat::Magazine => "",
+        None => return None,
+    };
+    let is_available = item
+        .get("isAvailable")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let is_holdable = item
+        .get("isHoldable")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let count = |key: &str| item.get(key).and_then(|v| v.as_i64());
+    let cover = item
+        .pointer("/covers/cover150Wide/href")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let title_id = item
+        .get("id")
+        .and_then(|v| {
+            v.as_str()
+                .map(str::to_string)
+                .or_else(|| v.as_i64().map(|id| id.to_string()))
+        })
+        .filter(|id| !id.is_empty());
+    // link straight to the title, ready to borrow, rather than to a search for it
+    let libby_url = title_id
+        .as_ref()
+        .map(|title_id| format!("{}/media/{}", library.libby_base_url, title_id))
+        .unwrap_or_else(|| libby_search_url.to_string());
+    // several editions of an audiobook are often only told apart by who reads them
+    let (narrator, duration) = if format == BookFormat::Audiobook {
+        (audiobook_narrator(item), audiobook_duration(item))
+    } else {
+        (None, None)
+    };
+    Some(LibbyLibraryBook {
+        cover: cover.to_string(),
+        title: title.trim().to_string(),
+        author: author.to_string(),
+        library: library.search_library.system_name.clone(),
+        format,
+        is_available,
+        is_holdable,
+        libby_search_url: libby_url,
+        title_id,
+        estimated_wait_days: count("estimatedWaitDays"),
+        holds_count: count("holdsCount"),
+        owned_copies: count("ownedCopies"),
+        available_copies: count("availableCopies"),
+        narrator,
+        duration,
+        publisher: item
+            .pointer("/publisher/name")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|publisher| !publisher.is_empty())
+            .map(str::to_string),
+        // e.g. "2015-09-29T04:00:00Z"
+        publication_year: item
+            .get("publishDate")
+            .and_then(|v| v.as_str())
+            .and_then(|date| date.get(..4))
+            .and_then(|year| year.parse::<i32>().ok()),
+    })
+}
+
+// the creators Overdrive lists with the "Narrator" role, e.g. "Jim Dale"
+fn audiobook_narrator(item: &Value) -> Option<String> {
+    let narrators = item
+        .get("creators")
+        .and_then(|v| v.as_array())?
+        .iter()
+        .filter(|creator| {
+            creator
+                .get("role")
+                .and_then(|v| v.as_str())
+                .is_some_and(|role| role.eq_ignore_ascii_case("narrator"))
+        })
+        .filter_map(|creator| creator.get("name").and_then(|v| v.as_str()))
+        .collect::<Vec<_>>();
+    (!narrators.is_empty()).then(|| narrators.join(", "))
+}
+
+// "hh:mm:ss", on the item itself or on one of its audiobook formats
+fn audiobook_duration(item: &Value) -> Option<String> {
+    let duration = |value: &Value| {
+        value
+            .get("duration")
+            .and_then(|v| v.as_str())
+            .filter(|duration| !duration.is_empty())
+            .map(str::to_string)
+    };
+    duration(item).or_else(|| {
+        item.get("formats")
+            .and_then(|v| v.as_array())?
+            .iter()
+            .find_map(duration)
+    })
+}
+
+/// Most StoryGraph to-read pages we read, in case a page keeps repeating itself.
+const STORYGRAPH_MAX_PAGES: usize = 50;
+
+/// Every book on a StoryGraph user's to-read list, each book only once. A leading "@" on the
+/// username is ignored.
+pub async fn storygraph_books(username: &str) -> Result<Vec<GoodreadsBook>, StorygraphError> {
+    let username = username.trim().trim_start_matches('@').to_string();
+    if username.is_empty() {
+        return Err(StorygraphError::MissingUsername);
+    }
+    let start = Instant::now();
+    let client = http_client();
+    let mut books = Vec::<GoodreadsBook>::new();
+    // compared normalized, the same way Goodreads shelves are deduplicated
+    let mut seen = std::collections::HashSet::new();
+    // the to-read list is an infinite scroll, so keep asking for pages until one adds nothing
+    for page in 1..=STORYGRAPH_MAX_PAGES {
+        let url = format!(
+            "https://app.thestorygraph.com/to-read/{}?page={}",
+            encode(&username),
+            page
+        );
+        info!(username = username, url = url, "Fetching StoryGraph books.");
+        let response = get_with_retry(client, &url).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!(username = username, "StoryGraph user not found.");
+            return Err(StorygraphError::NotFound(username));
+        }
+        let text = response.error_for_status()?.text().await?;
+        let page_books = tokio::task::spawn_blocking(move || parse_storygraph_books(&text)).await?;
+        let before = books.len();
+        books.extend(page_books.into_iter().filter(|book| {
+            seen.insert((normalize_title(&book.title), normalize_author(&book.author)))
+        }));
+        if books.len() == before {
+            break;
+        }
+    }
+    info!(
+        username = username,
+        total_books = books.len(),
+        duration = ?start.elapsed(),
+        "Finished fetching StoryGraph books."
+    );
+    Ok(books)
+}
+
+// Reads the books on one page of a StoryGraph to-read list. Every book is rendered twice,
+// once for the mobile layout and once for the desktop one, so books are deduplicated by id.
+fn parse_storygraph_books(html: &str) -> Vec<GoodreadsBook> {
+    let document = Html::parse_document(html);
+    let book_pane_selector = Selector::parse("div.book-pane[data-book-id]").unwrap();
+    let cover_selector = Selector::parse(".book-cover img").unwrap();
+    let title_selector =
+        Selector::parse(".book-title-author-and-series h3 a[href^='/books/']").unwrap();
+    let author_selector =
+        Selector::parse(".book-title-author-and-series a[href^='/authors/']").unwrap();
+    let series_selector =
+        Selector::parse(".book-title-author-and-series a[href^='/series/']").unwrap();
+
+    let mut seen_ids = std::collections::HashSet::new();
+    let mut books = Vec::new();
+    for book_pane in document.select(&book_pane_selector) {
+        let book_id = book_pane.value().attr("data-book-id").unwrap_or_default();
+        if !seen_ids.insert(book_id.to_string()) {
+            continue;
+        }
+        let text_of = |element: scraper::ElementRef| {
+            element
+                .text()
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let Some(title) = book_pane.select(&title_selector).next().map(text_of) else {
+            continue;
+        };
+        let authors = book_pane
+            .select(&author_selector)
+            .map(text_of)
+            .filter(|author| !author.is_empty())
+            .collect::<Vec<_>>();
+        let cover = book_pane
+            .select(&cover_selector)
+            .next()
+            .and_then(|element| element.value().attr("src"))
+            .unwrap_or_default()
+            .to_string();
+        let series = book_pane
+            .select(&series_selector)
+            .next()
+            .map(text_of)
+            .filter(|series| !series.is_empty());
+        books.push(GoodreadsBook {
+            cover,
+            title,
+            author: authors.first().cloned().unwrap_or_default(),
+            authors,
+            series,
+            // StoryGraph's list doesn't show these
+            date_added: None,
+            avg_rating: None,
+            isbn: None,
+            publication_year: None,
+        });
+    }
+    books
+}
+
+/// The catalog search hoopladigital.com runs, asking for just the fields we match on.
+const HOOPLA_SEARCH_QUERY: &str = "query FilterSearch($criteria: SearchCriteria!) { \
+    search(criteria: $criteria) { hits { id title kind { name } primaryArtist { name } } } }";
+
+/// Hits asked for per Hoopla search, only the first page is read.
+const HOOPLA_PER_PAGE: usize = 24;
+
+/// A book's title in Hoopla's catalog, matched by ISBN or by title and author, `None` when
+/// Hoopla doesn't have it.
+pub async fn hoopla_title(book: &GoodreadsBook) -> Result<Option<HooplaTitle>, HooplaError> {
+    let query = match &book.isbn {
+        Some(isbn) => isbn.clone(),
+        None => format!("{} {}", book.title, book.author).trim().to_string(),
+    };
+    let body = serde_json::json!({
+        "query": HOOPLA_SEARCH_QUERY,
+        "variables": {
+            "criteria": {
+                "q": query,
+                "availability": "ALL_TITLES",
+                "pagination": { "page": 1, "pageSize": HOOPLA_PER_PAGE },
+            },
+        },
+    });
+    let response = http_client()
+        .post("https://patron-api-gateway.hoopladigital.com/core/graphql")
+        .header("apollographql-client-name", "hoopla-www")
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let json: Value = serde_json::from_str(&response).map_err(|_| HooplaError::Malformed)?;
+    let hoopla_title = json
+        .pointer("/data/search/hits")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .find_map(|hit| {
+            let title = hit.get("title").and_then(|v| v.as_str())?;
+            let artist = hit
+                .pointer("/primaryArtist/name")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            // a pasted ISBN has no title of its own to compare
+            let isbn_only = book.isbn.as_deref() == Some(book.title.as_str());
+            let matches = isbn_only
+                || (titles_match(&book.title, title, MatchStrictness::default())
+                    && (book.authors.is_empty()
+                        || authors_match(&book.authors, artist, MatchStrictness::default())));
+            if !matches {
+                return None;
+            }
+            let id = hit.get("id").and_then(|v| {
+                v.as_str()
+                    .map(str::to_string)
+                    .or_else(|| v.as_i64().map(|id| id.to_string()))
+            })?;
+            Some(HooplaTitle {
+                title: title.trim().to_string(),
+                kind: hit
+                    .pointer("/kind/name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                hoopla_url: format!("https://www.hoopladigital.com/title/{}", id),
+            })
+        });
+    info!(
+        title = book.title,
+        found = hoopla_title.is_some(),
+        "Searched Hoopla."
+    );
+    Ok(hoopla_title)
+}
+
+/// Library systems matching a search, e.g. a city or library name, biggest first.
+pub async fn find_libraries(input: &str) -> Result<Vec<SearchLibrary>, LibraryError> {
+    let client = http_client();
+    let url = format!("https://libbyapp.com/api/locate/autocomplete/{}", input);
+    let response = get_with_retry(client, &url).await?.text().await?;
+    let json: Value = serde_json::from_str(&response).map_err(|_| LibraryError::Malformed)?;
+    info!(
+        search_input = input,
+        count = json.get("count").and_then(|v| v.as_i64()),
+        total = json.get("total").and_then(|v| v.as_i64()),
+        "Searching for library."
+    );
+    let branches = json
+        .get("branches")
+        .and_then(|v| v.as_array())
+        .ok_or(LibraryError::Malformed)?;
+    let mut libraries = Vec::<SearchLibrary>::new();
+    for branch in branches {
+        // find the library system for this branch, skipping branches without one
+        let Some(system) = branch.pointer("/systems/0") else {
+            continue;
+        };
+        let Some(system_name) = system.get("name").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        // then check if this system is already in the libraries list
+        if let Some(library) = libraries
+            .iter_mut()
+            .find(|lib| lib.system_name == system_name)
+        {
+            // if it is in the list, increment the branch count
+            library.branch_count += 1;
+        } else {
+            // if not, add it to the list
+            let (Some(fulfillment_id), Some(website_id)) = (
+                system.get("fulfillmentId").and_then(|v| v.as_str()),
+                system.get("websiteId").and_then(|v| v.as_i64()),
+            ) else {
+                continue;
+            };
+            let name = branch
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or(system_name);
+            libraries.push(SearchLibrary {
+                system_name: system_name.to_string(),
+                website_id: website_id.to_string(),
+                fulfillment_id: fulfillment_id.to_string(),
+                name: name.to_string(),
+                location: branch_location(branch),
+                branch_count: 1,
+            });
+        }
+    }
+
+    // big systems are the likely match for a city search, ties keep the autocomplete order
+    libraries.sort_by_key(|library| std::cmp::Reverse(library.branch_count));
+
+    let found_system_names = libraries
+        .iter()
+        .map(|lib| lib.system_name.clone())
+        .collect::<Vec<_>>()
+        .join(", ");
+    info!(
+        num_systems=libraries.len(),
+        found_system_names=?found_system_names,
+        "Found library systems via libby autocomplete."
+    );
+    Ok(libraries)
+}
+
+/// Every branch of a library system, for picking the one holds get picked up at.
+/// Availability is still checked for the whole system, which shares one catalog.
+pub async fn library_branches(
+    system_name: &str,
+    fulfillment_id: &str,
+) -> Result<Vec<LibraryBranch>, LibraryError> {
+    let client = http_client();
+    let url = format!(
+        "https://libbyapp.com/api/locate/autocomplete/{}",
+        encode(system_name)
+    );
+    let response = get_with_retry(client, &url).await?.text().await?;
+    let json: Value = serde_json::from_str(&response).map_err(|_| LibraryError::Malformed)?;
+    // the search also finds other systems' branches, keep only this system's
+    let mut branches = json
+        .get("branches")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|branch| {
+            branch
+                .pointer("/systems/0/fulfillmentId")
+                .and_then(|v| v.as_str())
+                == Some(fulfillment_id)
+        })
+        .filter_map(|branch| {
+            Some(LibraryBranch {
+                name: branch.get("name").and_then(|v| v.as_str())?.to_string(),
+                location: branch_location(branch),
+            })
+        })
+        .collect::<Vec<_>>();
+    branches.sort_by(|a, b| a.name.cmp(&b.name));
+    branches.dedup_by(|a, b| a.name == b.name);
+    info!(
+        system_name = system_name,
+        num_branches = branches.len(),
+        "Found library branches via libby autocomplete."
+    );
+    Ok(branches)
+}
+
+/// "City, Region" for an autocomplete branch, from whichever of those the branch has. The
+/// address is sometimes nested and sometimes flattened onto the branch.
+fn branch_location(branch: &Value) -> String {
+    let field = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            branch
+                .pointer(&format!("/address/{}", key))
+                .or_else(|| branch.get(*key))
+                .and_then(|v| v.as_str())
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+        })
+    };
+    [field(&["city"]), field(&["region", "state"])]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A library by its Libby website id, e.g. "50", as used in share links.
+pub async fn library_from_website_id(website_id: &str) -> Result<Library, LibraryError> {
+    let cached = library_cache()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get(website_id)
+        .cloned();
+    if let Some(library) = cached {
+        return Ok(library);
+    }
+    let library = fetch_library_from_website_id(website_id).await?;
+    library_cache()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(website_id.to_string(), library.clone());
+    Ok(library)
+}
+
+/// Libraries by website id. Library metadata effectively never changes, so entries live as
+/// long as the process does.
+fn library_cache() -> &'static Mutex<HashMap<String, Library>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Library>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+async fn fetch_library_from_website_id(website_id: &str) -> Result<Library, LibraryError> {
+    let system_id_url = format!(
+        "https://thunder.api.overdrive.com/v2/libraries/?websiteid={}",
+        website_id
+    );
+    let client = http_client();
+    let library_json = get_with_retry(client, &system_id_url).await?.text().await?;
+    let library_value: Value = serde_json::from_str(&library_json)?;
+    // an unknown website id answers with no items
+    let item = &library_value["items"][0];
+    let not_found = || LibraryError::NotFound(website_id.to_string());
+    let system_id = item["id"].as_str().ok_or_else(not_found)?;
+    let fulfillment_id = item["fulfillmentId"].as_str().ok_or_else(not_found)?;
+    let name = item["name"].as_str().ok_or_else(not_found)?;
+    let libby_base_url = format!("https://libbyapp.com/library/{}", system_id);
+    let overdrive_base_url = format!(
+        "https://thunder.api.overdrive.com/v2/libraries/{}",
+        system_id
+    );
+    info!(
+        website_id = website_id,
+        method = "get_library_from_website_id",
+        "Found library system!"
+    );
+    let search_lib = SearchLibrary {
+        system_name: name.to_string(),
+        website_id: website_id.to_string(),
+        fulfillment_id: fulfillment_id.to_string(),
+        name: name.to_string(),
+        location: String::new(),
+        branch_count: 1,
+    };
+    Ok(Library {
+        search_library: search_lib,
+        system_id: system_id.to_string(),
+        libby_base_url,
+        overdrive_base_url,
+    })
+}
+
+/// A library by its Overdrive system id, e.g. "hawaii", as in its libbyapp.com address.
+pub async fn library_from_system_id(system_id: &str) -> Result<Library, LibraryError> {
+    let system_id = system_id.trim().to_lowercase();
+    let system_id_url = format!(
+        "https://thunder.api.overdrive.com/v2/libraries/{}",
+        encode(&system_id)
+    );
+    let client = http_client();
+    let library_json = get_with_retry(client, &system_id_url).await?.text().await?;
+    // an unknown id answers with an error object, which has none of these fields
+    let library_value: Value = serde_json::from_str(&library_json)?;
+    let not_found = || LibraryError::NotFound(system_id.clone());
+    let name = library_value["name"].as_str().ok_or_else(not_found)?;
+    // usually a string here, but a number in the autocomplete results
+    let website_id = match &library_value["websiteId"] {
+        Value::String(website_id) => website_id.clone(),
+        Value::Number(website_id) => website_id.to_string(),
+        _ => return Err(not_found()),
+    };
+    let fulfillment_id = library_value["fulfillmentId"]
+        .as_str()
+        .ok_or_else(not_found)?;
+    let libby_base_url = format!("https://libbyapp.com/library/{}", system_id);
+    let overdrive_base_url = format!(
+        "https://thunder.api.overdrive.com/v2/libraries/{}",
+        system_id
+    );
+    let search_lib = SearchLibrary {
+        system_name: name.to_string(),
+        website_id: website_id.to_string(),
+        fulfillment_id: fulfillment_id.to_string(),
+        name: name.to_string(),
+        location: String::new(),
+        branch_count: 1,
+    };
+    info!(
+        search_lib = ?search_lib,
+        method = "get_library_from_system_id",
+        "Found library system."
+    );
+    Ok(Library {
+        search_library: search_lib,
+        system_id: system_id.to_string(),
+        libby_base_url,
+        overdrive_base_url,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn every_page_is_fetched_within_the_cap() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let permits = Arc::new(tokio::sync::Semaphore::new(3));
+        let pages = fetch_pages(1..=20, permits, |page_number| {
+            let running = Arc::clone(&running);
+            let most_running = Arc::clone(&most_running);
+            async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(page_number * 10)
+            }
+        })
+        .await;
+
+        let fetched = pages
+            .into_iter()
+            .map(|(page_number, result)| (page_number, result.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fetched,
+            (1..=20).map(|page| (page, page * 10)).collect::<Vec<_>>()
+        );
+        assert!(most_running.load(Ordering::SeqCst) <= 3);
+    }
+
+    const SHELF_PAGE: &str = include_str!("../tests/fixtures/goodreads_shelf_page.html");
+
+    #[test]
+    fn parses_every_field_of_a_shelf_page() {
+        let books = parse_goodreads_books(SHELF_PAGE);
+        assert_eq!(books.len(), 3);
+
+        let night_circus = &books[0];
+        assert_eq!(night_circus.title, "The Night Circus");
+        assert_eq!(night_circus.author, "Morgenstern, Erin");
+        assert_eq!(night_circus.series, None);
+        assert_eq!(night_circus.isbn.as_deref(), Some("9780385534635"));
+        assert_eq!(night_circus.avg_rating, Some(4.03));
+        assert_eq!(night_circus.publication_year, Some(2011));
+        assert_eq!(night_circus.date_added, NaiveDate::from_ymd_opt(2021, 3, 3));
+        assert_eq!(
+            night_circus.cover,
+            "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1387124618i/9361589._SX98_.jpg"
+        );
+
+        let darker_shade = &books[1];
+        assert_eq!(darker_shade.title, "A Darker Shade of Magic");
+        assert_eq!(darker_shade.series.as_deref(), Some("Shades of Magic, #1"));
+        assert_eq!(darker_shade.isbn, None);
+
+        let good_omens = &books[2];
+        assert_eq!(
+            good_omens.authors,
+            vec!["Gaiman, Neil".to_string(), "Pratchett, Terry".to_string()]
+        );
+        assert_eq!(good_omens.author, "Gaiman, Neil");
+        assert_eq!(good_omens.cover, COVER_PLACEHOLDER);
+        assert_eq!(good_omens.avg_rating, None);
+        assert_eq!(good_omens.publication_year, None);
+        assert_eq!(good_omens.date_added, NaiveDate::from_ymd_opt(2023, 7, 4));
+    }
+
+    #[test]
+    fn parses_every_row_of_a_full_page() {
+        let (head, rest) = SHELF_PAGE.split_once("<tr ").unwrap();
+        let (first_row, _) = rest.split_once("</tr>").unwrap();
+        let (_, tail) = SHELF_PAGE.rsplit_once("</tr>").unwrap();
+        let rows = (0..GOODREADS_PER_PAGE)
+            .map(|index| {
+                format!("<tr {}</tr>", first_row).replace(
+                    "The Night Circus\n",
+                    &format!("The Night Circus {}\n", index),
+                )
+            })
+            .collect::<String>();
+        let books = parse_goodreads_books(&format!("{}{}{}", head, rows, tail));
+
+        assert_eq!(books.len(), GOODREADS_PER_PAGE as usize);
+        for (index, book) in books.iter().enumerate() {
+            assert_eq!(book.title, format!("The Night Circus {}", index));
+            assert_eq!(book.author, "Morgenstern, Erin");
+        }
+    }
+
+    #[test]
+    fn skips_rows_without_a_title() {
+        let (head, rest) = SHELF_PAGE.split_once("<td class=\"field title\">").unwrap();
+        let (_, tail) = rest.split_once("</td>").unwrap();
+        let books = parse_goodreads_books(&format!("{}{}", head, tail));
+        assert_eq!(books.len(), 2);
+        assert_eq!(books[0].title, "A Darker Shade of Magic");
+    }
+
+    #[test]
+    fn shelf_urls_are_percent_encoded() {
+        assert_eq!(
+            goodreads_shelf_url("12345678", "to-read"),
+            "https://goodreads.com/review/list/12345678?print=true&shelf=to-read&per_page=100"
+        );
+        assert_eq!(
+            goodreads_shelf_url("12345678", "sci-fi & fantasy #2"),
+            "https://goodreads.com/review/list/12345678?print=true&shelf=sci-fi%20%26%20fantasy%20%232&per_page=100"
+        );
+    }
+
+    const ELIDED_SHELF_PAGE: &str =
+        include_str!("../tests/fixtures/goodreads_shelf_page_elided.html");
+
+    #[test]
+    fn counts_every_page_when_the_middle_ones_are_elided() {
+        assert_eq!(parse_goodreads_page_count(ELIDED_SHELF_PAGE).unwrap(), 20);
+        assert_eq!(parse_goodreads_page_count(SHELF_PAGE).unwrap(), 1);
+    }
+
+    #[test]
+    fn counts_pages_from_the_last_link_text() {
+        let without_count_or_href = ELIDED_SHELF_PAGE
+            .replace("Want to Read (1,942)", "Want to Read")
+            .replace("page=19&amp;", "")
+            .replace("page=20&amp;", "");
+        assert_eq!(
+            parse_goodreads_page_count(&without_count_or_href).unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn counts_pages_from_the_last_link_href() {
+        let without_count_or_text = ELIDED_SHELF_PAGE
+            .replace("Want to Read (1,942)", "Want to Read")
+            .replace(">19</a>", ">…</a>")
+            .replace(">20</a>", ">last »</a>");
+        assert_eq!(
+            parse_goodreads_page_count(&without_count_or_text).unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn counts_pages_from_the_shelf_book_count() {
+        let (head, rest) = ELIDED_SHELF_PAGE
+            .split_once("<div id=\"reviewPagination\">")
+            .unwrap();
+        let (_, tail) = rest.split_once("</div>").unwrap();
+        let without_pagination = format!("{}{}", head, tail);
+        assert_eq!(parse_goodreads_page_count(&without_pagination).unwrap(), 20);
+        assert_eq!(shelf_book_count("Want to Read (1,942)"), Some(1942));
+        assert_eq!(shelf_book_count("Want to Read"), None);
+    }
+
+    fn book(title: &str, author: &str) -> GoodreadsBook {
+        GoodreadsBook {
+            cover: String::new(),
+            title: title.to_string(),
+            author: author.to_string(),
+            authors: vec![author.to_string()],
+            series: None,
+            date_added: None,
+            avg_rating: None,
+            isbn: None,
+            publication_year: None,
+        }
+    }
+
+    fn library_book(
+        library: &str,
+        is_available: bool,
+        is_holdable: bool,
+        estimated_wait_days: Option<i64>,
+    ) -> LibbyLibraryBook {
+        LibbyLibraryBook {
+            cover: String::new(),
+            title: "The Night Circus".to_string(),
+            author: "Erin Morgenstern".to_string(),
+            library: library.to_string(),
+            format: BookFormat::Ebook,
+            is_available,
+            is_holdable,
+            libby_search_url: format!("https://libbyapp.com/library/{}/media/1", library),
+            title_id: Some("1".to_string()),
+            estimated_wait_days,
+            holds_count: None,
+            owned_copies: None,
+            available_copies: is_available.then_some(1),
+            narrator: None,
+            duration: None,
+            publisher: None,
+            publication_year: None,
+        }
+    }
+
+    #[test]
+    fn an_available_library_beats_an_earlier_holdable_one() {
+        let books = [
+            library_book("notowned", false, false, None),
+            library_book("holdable", false, true, Some(7)),
+            library_book("available", true, true, None),
+            library_book("alsoavailable", true, true, None),
+        ];
+        let picked = pick_availability(books.iter()).unwrap();
+        assert!(picked.is_available && picked.is_holdable);
+        assert_eq!(
+            picked.libby_search_url,
+            "https://libbyapp.com/library/available/media/1"
+        );
+        assert_eq!(picked.estimated_wait_days, None);
+    }
+
+    #[test]
+    fn the_shortest_known_wait_wins_among_holdable_libraries() {
+        let books = [
+            library_book("unknownwait", false, true, None),
+            library_book("longwait", false, true, Some(60)),
+            library_book("shortwait", false, true, Some(14)),
+            library_book("alsoshortwait", false, true, Some(14)),
+        ];
+        let picked = pick_availability(books.iter()).unwrap();
+        assert!(!picked.is_available && picked.is_holdable);
+        assert_eq!(
+            picked.libby_search_url,
+            "https://libbyapp.com/library/shortwait/media/1"
+        );
+        assert_eq!(picked.estimated_wait_days, Some(14));
+        assert_eq!(picked.available_copies, None);
+    }
+
+    #[test]
+    fn nothing_to_pick_from_falls_back_to_the_first_library() {
+        let books = [
+            library_book("first", false, false, None),
+            library_book("second", false, false, None),
+        ];
+        let picked = pick_availability(books.iter()).unwrap();
+        assert!(!picked.is_available && !picked.is_holdable);
+        assert_eq!(
+            picked.libby_search_url,
+            "https://libbyapp.com/library/first/media/1"
+        );
+        assert!(pick_availability(std::iter::empty()).is_none());
+    }
+
+    // stands in for Overdrive, answering every request with `body` and counting them. the
+    // returned library searches it
+    async fn fake_overdrive(
+        body: &'static str,
+        content_type: &'static str,
+    ) -> (Library, Arc<AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let library = Library {
+            search_library: SearchLibrary {
+                system_name: "Test Library".to_string(),
+                website_id: "1".to_string(),
+                fulfillment_id: "test".to_string(),
+                name: "Test Library".to_string(),
+                location: String::new(),
+                branch_count: 1,
+            },
+            system_id: format!("test-{}", port),
+            libby_base_url: "https://libbyapp.com/library/test".to_string(),
+            overdrive_base_url: format!("http://127.0.0.1:{}", port),
+        };
+        (library, requests)
+    }
+
+    #[tokio::test]
+    async fn a_repeat_library_check_is_answered_from_the_cache() {
+        let (library, searches) = fake_overdrive(r#"{"items":[]}"#, "application/json").await;
+        let client = Client::builder().no_proxy().build().unwrap();
+        let check = |title: &str, strictness, bypass_cache| {
+            let book = book(title, "Erin Morgenstern");
+            let (client, library) = (&client, &library);
+            async move {
+                check_library(
+                    client,
+                    &book,
+                    library,
+                    &[BookFormat::Ebook],
+                    &[],
+                    false,
+                    strictness,
+                    bypass_cache,
+                )
+                .await
+            }
+        };
+
+        let first = check("The Night Circus", MatchStrictness::Fuzzy, false)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 1);
+        let second = check("The Night Circus", MatchStrictness::Fuzzy, false)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 1);
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert!(!second[0].is_available && !second[0].is_holdable);
+
+        // the same title once normalized shares the entry, unless matching exactly
+        check("Night Circus, The", MatchStrictness::Fuzzy, false)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 1);
+        check("The Night Circus", MatchStrictness::Exact, false)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 2);
+        check("Night Circus, The", MatchStrictness::Exact, false)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 3);
+
+        // a re-check always asks Overdrive again
+        check("The Night Circus", MatchStrictness::Fuzzy, true)
+            .await
+            .unwrap();
+        assert_eq!(searches.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn a_response_that_isnt_json_is_an_error_not_a_miss() {
+        let (library, _) =
+            fake_overdrive("<html><body>Access denied</body></html>", "text/html").await;
+        let client = Client::builder().no_proxy().build().unwrap();
+        let book = book("The Night Circus", "Erin Morgenstern");
+
+        let searched = check_library(
+            &client,
+            &book,
+            &library,
+            &[BookFormat::Ebook],
+            &[],
+            false,
+            MatchStrictness::default(),
+            true,
+        )
+        .await;
+        assert!(matches!(searched, Err(AvailabilityError::Malformed)));
+        let pinned = fetch_pinned(&client, &library, "1234567", &[BookFormat::Ebook]).await;
+        assert!(matches!(pinned, Err(AvailabilityError::Malformed)));
+    }
+
+    #[tokio::test]
+    async fn no_libraries_is_an_error_rather_than_a_panic() {
+        let result = libby_availability(
+            &book("The Night Circus", "Erin Morgenstern"),
+            &[],
+            &[BookFormat::Ebook],
+            &[],
+            false,
+            MatchStrictness::default(),
+            None,
+            false,
+        )
+        .await;
+        assert!(matches!(result, Err(AvailabilityError::NoLibraries)));
+    }
+}
@@ -1,20 +1,31 @@
 #[cfg(feature = "ssr")]
 #[tokio::main]
 async fn main() {
+    use axum::http::{header, StatusCode};
+    use axum::routing::get;
     use axum::Router;
     use dotenv::dotenv;
     use leptos::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
+    use libbyreads_rs::api;
     use libbyreads_rs::app::*;
+    use libbyreads_rs::config::config;
     use libbyreads_rs::fileserv::file_and_error_handler;
+    use libbyreads_rs::http::http_client;
+    use libbyreads_rs::notify;
+    use libbyreads_rs::refresh;
+    use libbyreads_rs::telemetry::{self, OtelSpanLayer};
+    use opentelemetry::trace::TracerProvider as _;
     use opentelemetry::KeyValue;
     use opentelemetry_appender_tracing::layer;
     use opentelemetry_otlp::WithExportConfig;
     use opentelemetry_sdk::logs::LoggerProvider;
     use opentelemetry_sdk::Resource;
     use std::env;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
     use std::time::Duration;
-    use tracing::info;
+    use tracing::{info, warn};
     use tracing_subscriber;
     use tracing_subscriber::EnvFilter;
     use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -22,7 +33,16 @@ async fn main() {
     dotenv().ok();
 
     let environment = env::var("ENV").expect("ENV not set");
-    if environment == "local" {
+    // logs only go to Honeycomb when all of its settings are there
+    let honeycomb = match (
+        env::var("HONEYCOMB_LOG_API_ENDPOINT"),
+        env::var("HONEYCOMB_API_KEY"),
+        env::var("HONEYCOMB_DATASET"),
+    ) {
+        (Ok(endpoint), Ok(api_key), Ok(dataset)) => Some((endpoint, api_key, dataset)),
+        _ => None,
+    };
+    if environment == "local" || honeycomb.is_none() {
         // TODO
         tracing_subscriber::registry()
             .with(
@@ -31,28 +51,31 @@ async fn main() {
             )
             .with(tracing_subscriber::fmt::layer())
             .init();
-    } else {
-        let export_config = opentelemetry_otlp::ExportConfig {
-            endpoint: env::var("HONEYCOMB_LOG_API_ENDPOINT")
-                .expect("HONEYCOMB_LOG_API_ENDPOINT not set"),
+        if environment != "local" {
+            warn!(
+                "HONEYCOMB_LOG_API_ENDPOINT, HONEYCOMB_API_KEY or HONEYCOMB_DATASET not set, \
+                logging to stdout instead of Honeycomb."
+            );
+        }
+    } else if let Some((endpoint, api_key, dataset)) = honeycomb {
+        // traces go next to the logs unless told otherwise, e.g. .../v1/logs -> .../v1/traces
+        let trace_endpoint = env::var("HONEYCOMB_TRACE_API_ENDPOINT")
+            .unwrap_or_else(|_| endpoint.replace("/v1/logs", "/v1/traces"));
+        let export_config = |endpoint: String| opentelemetry_otlp::ExportConfig {
+            endpoint,
             protocol: opentelemetry_otlp::Protocol::HttpBinary,
             timeout: Duration::from_secs(3),
         };
+        let headers = {
+            let mut headers = std::collections::HashMap::new();
+            headers.insert("x-honeycomb-team".to_string(), api_key);
+            headers.insert("x-honeycomb-dataset".to_string(), dataset);
+            headers
+        };
         let log_exporter = opentelemetry_otlp::new_exporter()
             .http()
-            .with_export_config(export_config)
-            .with_headers({
-                let mut headers = std::collections::HashMap::new();
-                headers.insert(
-                    "x-honeycomb-team".to_string(),
-                    env::var("HONEYCOMB_API_KEY").expect("HONEYCOMB_API_KEY not set"),
-                );
-                headers.insert(
-                    "x-honeycomb-dataset".to_string(),
-                    env::var("HONEYCOMB_DATASET").expect("HONEYCOMB_DATASET not set"),
-                );
-                headers
-            })
+            .with_export_config(export_config(endpoint))
+            .with_headers(headers.clone())
             .build_log_exporter()
             .unwrap();
         let resource = Resource::new(vec![
@@ -61,20 +84,36 @@ async fn main() {
         ]);
         let logger_provider = LoggerProvider::builder()
             .with_batch_exporter(log_exporter, opentelemetry_sdk::runtime::Tokio)
-            .with_resource(resource)
+            .with_resource(resource.clone())
             .build();
+        let tracer_provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_export_config(export_config(trace_endpoint))
+                    .with_headers(headers),
+            )
+            .with_trace_config(opentelemetry_sdk::trace::Config::default().with_resource(resource))
+            .install_batch(opentelemetry_sdk::runtime::Tokio)
+            .unwrap();
+        opentelemetry::global::set_tracer_provider(tracer_provider.clone());
 
         let logger_layer = layer::OpenTelemetryTracingBridge::new(&logger_provider);
+        let span_layer = OtelSpanLayer::new(tracer_provider.tracer("libbyreads"));
         let env_filter_layer = EnvFilter::try_from_default_env()
             .or_else(|_| EnvFilter::try_new("info"))
             .unwrap();
 
         let subscriber = tracing_subscriber::registry().with(env_filter_layer);
 
-        tracing::subscriber::set_global_default(subscriber.with(logger_layer)).unwrap();
+        tracing::subscriber::set_global_default(subscriber.with(logger_layer).with(span_layer))
+            .unwrap();
     }
 
     console_error_panic_hook::set_once();
+    // before anything is counted, so nothing recorded at startup is lost
+    telemetry::prometheus();
 
     info!("Starting server");
     // Setting get_configuration(None) means we'll be using cargo-leptos's env values
@@ -86,18 +125,64 @@ async fn main() {
     let leptos_options = conf.leptos_options;
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(App);
+    // flipped once the routes are built and the shared client exists, for /readyz
+    let ready = Arc::new(AtomicBool::new(false));
 
     // build our application with a route
     let app = Router::new()
+        // probes for the load balancer, cheap enough to hit every few seconds
+        .route("/healthz", get(|| async { "ok" }))
+        .route(
+            "/metrics",
+            get(|| async {
+                (
+                    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    telemetry::prometheus().render(),
+                )
+            }),
+        )
+        .route(
+            "/readyz",
+            get({
+                let ready = Arc::clone(&ready);
+                move || async move {
+                    if ready.load(Ordering::Relaxed) {
+                        (StatusCode::OK, "ready")
+                    } else {
+                        (StatusCode::SERVICE_UNAVAILABLE, "starting")
+                    }
+                }
+            }),
+        )
+        .route("/api/availability", get(api::availability))
+        .route("/api/availability/stream", get(api::availability_stream))
+        .route("/api/availability.ics", get(api::availability_ics))
+        .route("/api/confirm", get(api::confirm))
+        .route("/api/unsubscribe", get(api::unsubscribe))
         .leptos_routes(&leptos_options, routes, App)
         .fallback(file_and_error_handler)
         .with_state(leptos_options);
 
+    // build the shared client now rather than on the first request
+    http_client();
+    ready.store(true, Ordering::Relaxed);
+
+    refresh::spawn_refresher();
+    if config().smtp.is_some() {
+        notify::spawn_notifier();
+    } else {
+        info!("SMTP_HOST, SMTP_USERNAME, SMTP_PASSWORD or EMAIL_FROM not set, no availability emails.");
+    }
+
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     info!("listening on http://{}", &addr);
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    // the peer address is what sign-ups are rate limited by
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 #[cfg(not(feature = "ssr"))]
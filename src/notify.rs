@@ -0,0 +1,358 @@
+use crate::cache::TtlCache;
+use crate::config::config;
+use crate::matching::MatchStrictness;
+use crate::refresh::{self, Watch};
+use crate::smtp::{self, SmtpError};
+use crate::status_store;
+use crate::types::{BookFormat, LibbyBook};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// How long a confirmation link works. Unconfirmed subscriptions are dropped after this.
+const CONFIRMATION_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// How long an address has to wait before it can be sent another confirmation email.
+const ADDRESS_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Most sign-ups one IP address can make before it has to go quiet for SIGNUP_WINDOW.
+const MAX_SIGNUPS_PER_IP: u32 = 5;
+const SIGNUP_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Someone who asked to be emailed when a book on their shelves becomes available.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Subscription {
+    /// Random, and the only thing the confirmation and unsubscribe links need.
+    pub id: String,
+    pub email: String,
+    pub user_id: String,
+    pub shelves: Vec<String>,
+    pub library_website_ids: Vec<String>,
+    pub formats: Vec<BookFormat>,
+    /// Whether the link in the confirmation email was opened. Only confirmed subscriptions
+    /// are checked or emailed about.
+    #[serde(default)]
+    pub confirmed: bool,
+    #[serde(default)]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why a sign-up was turned down.
+#[derive(Debug, Error)]
+pub enum SubscribeError {
+    #[error("A confirmation email was sent to that address recently, check your inbox")]
+    AddressCooldown,
+    #[error("Too many sign-ups from your network, try again later")]
+    TooManySignups,
+    #[error("Could not send the confirmation email: {0}")]
+    Smtp(#[from] SmtpError),
+}
+
+static SUBSCRIPTIONS: OnceLock<Mutex<Vec<Subscription>>> = OnceLock::new();
+
+// bumped by every save, so a write that's been overtaken by a newer one can skip itself
+static SAVES: AtomicU64 = AtomicU64::new(0);
+static WRITING: Mutex<()> = Mutex::new(());
+
+// loaded from SUBSCRIPTIONS_PATH on first use, if it's set and exists. A file that doesn't parse
+// is moved aside rather than overwritten by the next save
+fn subscriptions() -> std::sync::MutexGuard<'static, Vec<Subscription>> {
+    SUBSCRIPTIONS
+        .get_or_init(|| {
+            let Some(path) = &config().subscriptions_path else {
+                return Mutex::new(Vec::new());
+            };
+            let Ok(json) = fs::read_to_string(path) else {
+                return Mutex::new(Vec::new());
+            };
+            match serde_json::from_str(&json) {
+                Ok(saved) => Mutex::new(saved),
+                Err(err) => {
+                    let aside = path.with_extension("json.corrupt");
+                    warn!(path = ?path, aside = ?aside, error = %err, "Could not read email subscriptions, starting over.");
+                    if let Err(err) = fs::rename(path, &aside) {
+                        warn!(path = ?path, error = %err, "Could not move unreadable subscriptions aside.");
+                    }
+                    Mutex::new(Vec::new())
+                }
+            }
+        })
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+// writes to SUBSCRIPTIONS_PATH on a blocking thread, through a temporary file that's renamed
+// over it so a crash mid-write can't leave half a file
+fn save(subscriptions: &[Subscription]) {
+    let Some(path) = config().subscriptions_path.clone() else {
+        return;
+    };
+    let json = match serde_json::to_string(subscriptions) {
+        Ok(json) => json,
+        Err(err) => {
+            warn!(path = ?path, error = %err, "Could not save email subscriptions.");
+            return;
+        }
+    };
+    let save = SAVES.fetch_add(1, Ordering::SeqCst) + 1;
+    tokio::task::spawn_blocking(move || {
+        let _writing = WRITING.lock().unwrap_or_else(|err| err.into_inner());
+        if SAVES.load(Ordering::SeqCst) != save {
+            return;
+        }
+        let temporary = path.with_extension("json.tmp");
+        let written = fs::write(&temporary, json).and_then(|()| fs::rename(&temporary, &path));
+        if let Err(err) = written {
+            warn!(path = ?path, error = %err, "Could not save email subscriptions.");
+        }
+    });
+}
+
+// confirmation emails sent lately, by lowercased address
+fn recent_addresses() -> &'static TtlCache<String, ()> {
+    static ADDRESSES: OnceLock<TtlCache<String, ()>> = OnceLock::new();
+    ADDRESSES.get_or_init(|| TtlCache::new(ADDRESS_COOLDOWN))
+}
+
+// sign-ups per IP address, counted until the address has been quiet for SIGNUP_WINDOW
+fn recent_signups() -> &'static TtlCache<IpAddr, u32> {
+    static SIGNUPS: OnceLock<TtlCache<IpAddr, u32>> = OnceLock::new();
+    SIGNUPS.get_or_init(|| TtlCache::new(SIGNUP_WINDOW))
+}
+
+/// Adds an unconfirmed subscription and emails its confirmation link. Nothing else is sent to
+/// the address until that link is opened, and each address and IP address can only ask so
+/// often.
+pub async fn subscribe(
+    email: String,
+    user_id: String,
+    shelves: Vec<String>,
+    library_website_ids: Vec<String>,
+    formats: Vec<BookFormat>,
+    ip: Option<IpAddr>,
+) -> Result<Subscription, SubscribeError> {
+    let address_key = email.to_lowercase();
+    if recent_addresses().get(&address_key).is_some() {
+        return Err(SubscribeError::AddressCooldown);
+    }
+    if let Some(ip) = ip {
+        let signups = recent_signups().get(&ip).unwrap_or_default();
+        if signups >= MAX_SIGNUPS_PER_IP {
+            return Err(SubscribeError::TooManySignups);
+        }
+        recent_signups().insert(ip, signups + 1);
+    }
+    recent_addresses().insert(address_key, ());
+
+    let subscription = Subscription {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        email,
+        user_id,
+        shelves,
+        library_website_ids,
+        formats,
+        confirmed: false,
+        created_at: Utc::now(),
+    };
+    if let Some(smtp_config) = &config().smtp {
+        let body = format!(
+            "Someone, hopefully you, asked to be emailed when books on Goodreads user {}'s \
+             shelves become available at their libraries.\n\n\
+             Confirm: {}/api/confirm?id={}\n\n\
+             If it wasn't you, ignore this email and nothing more will be sent.\n",
+            subscription.user_id,
+            config().public_url,
+            subscription.id
+        );
+        smtp::send(
+            smtp_config,
+            &subscription.email,
+            "Confirm your libbyreads emails",
+            &body,
+        )
+        .await?;
+    }
+    // an earlier unconfirmed sign-up is replaced, a confirmed one is kept until this one is
+    // confirmed
+    let mut subscriptions = subscriptions();
+    subscriptions.retain(|existing| existing.confirmed || !existing.is_same_signup(&subscription));
+    subscriptions.push(subscription.clone());
+    save(&subscriptions);
+    Ok(subscription)
+}
+
+/// Confirms a subscription from its emailed link, replacing any earlier one for the same email
+/// and Goodreads user so changing libraries or shelves doesn't mean two emails per book.
+/// False if there's no unconfirmed subscription with that id, or its link has expired.
+pub fn confirm(id: &str) -> bool {
+    let mut subscriptions = subscriptions();
+    let Some(confirmed) = subscriptions
+        .iter()
+        .find(|subscription| {
+            subscription.id == id && !subscription.confirmed && !subscription.is_expired()
+        })
+        .cloned()
+    else {
+        return false;
+    };
+    subscriptions
+        .retain(|existing| existing.id == confirmed.id || !existing.is_same_signup(&confirmed));
+    for subscription in subscriptions.iter_mut() {
+        if subscription.id == confirmed.id {
+            subscription.confirmed = true;
+        }
+    }
+    save(&subscriptions);
+    true
+}
+
+/// Removes a subscription, false if there was none with that id.
+pub fn unsubscribe(id: &str) -> bool {
+    let mut subscriptions = subscriptions();
+    let before = subscriptions.len();
+    subscriptions.retain(|subscription| subscription.id != id);
+    let removed = subscriptions.len() != before;
+    if removed {
+        save(&subscriptions);
+    }
+    removed
+}
+
+impl Subscription {
+    fn is_same_signup(&self, other: &Subscription) -> bool {
+        self.email.eq_ignore_ascii_case(&other.email) && self.user_id == other.user_id
+    }
+
+    // an unconfirmed subscription whose link no longer works
+    fn is_expired(&self) -> bool {
+        !self.confirmed
+            && Utc::now()
+                .signed_duration_since(self.created_at)
+                .to_std()
+                .unwrap_or_default()
+                > CONFIRMATION_TTL
+    }
+
+    fn watch(&self) -> Watch {
+        Watch::new(
+            &self.user_id,
+            self.shelves.clone(),
+            self.library_website_ids.clone(),
+            self.formats.clone(),
+            Vec::new(),
+            true,
+            MatchStrictness::default(),
+        )
+    }
+}
+
+/// Checks every confirmed subscription again each NOTIFY_INTERVAL_SECS, emailing the books that
+/// became available since the previous check. The first check only records where things stand.
+/// Subscriptions are kept on the background refresh, whose results are used when recent.
+/// Unconfirmed subscriptions are dropped once their link expires.
+pub fn spawn_notifier() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(config().notify_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let due = {
+                let mut subscriptions = subscriptions();
+                let before = subscriptions.len();
+                subscriptions.retain(|subscription| !subscription.is_expired());
+                if subscriptions.len() != before {
+                    save(&subscriptions);
+                }
+                subscriptions
+                    .iter()
+                    .filter(|subscription| subscription.confirmed)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            info!(subscriptions = due.len(), "Checking email subscriptions.");
+            for subscription in due {
+                if let Err(err) = notify(&subscription).await {
+                    warn!(
+                        user_id = subscription.user_id,
+                        error = err,
+                        "Could not check subscription."
+                    );
+                }
+            }
+        }
+    });
+}
+
+async fn notify(subscription: &Subscription) -> Result<(), String> {
+    let Some(smtp_config) = &config().smtp else {
+        return Ok(());
+    };
+    let watch = subscription.watch();
+    refresh::watch(&watch);
+    let availability = match refresh::cached(&watch) {
+        Some(refreshed) => refreshed.availability,
+        None => refresh::check(&watch)
+            .await
+            .map_err(|err| err.to_string())?,
+    };
+
+    let statuses = availability
+        .iter()
+        .flat_map(LibbyBook::library_statuses)
+        .collect::<Vec<_>>();
+    // kept apart from the user's own searches, so searching in the browser doesn't use up
+    // the "newly available" an email would have been about
+    let newly_available =
+        status_store::record(&format!("subscription:{}", subscription.id), &statuses)
+            .await
+            .map_err(|err| err.to_string())?;
+    if newly_available.is_empty() {
+        return Ok(());
+    }
+
+    let mut body = String::from("Now available to borrow:\n\n");
+    for book_status in &newly_available {
+        body.push_str(&format!(
+            "{} by {} at {}\n",
+            book_status.title, book_status.author, book_status.library
+        ));
+        let url = availability
+            .iter()
+            .filter(|libby_book| {
+                libby_book.title == book_status.title && libby_book.author == book_status.author
+            })
+            .flat_map(|libby_book| &libby_book.library_books)
+            .find(|library_book| {
+                library_book.library == book_status.library && library_book.is_available
+            })
+            .map(|library_book| library_book.libby_search_url.clone());
+        if let Some(url) = url {
+            body.push_str(&format!("{}\n", url));
+        }
+        body.push('\n');
+    }
+    body.push_str(&format!(
+        "Stop these emails: {}/api/unsubscribe?id={}\n",
+        config().public_url,
+        subscription.id
+    ));
+    let subject = match newly_available.as_slice() {
+        [book_status] => format!("{} is available", book_status.title),
+        _ => format!("{} books are available", newly_available.len()),
+    };
+    smtp::send(smtp_config, &subscription.email, &subject, &body)
+        .await
+        .map_err(|err| err.to_string())?;
+    info!(
+        user_id = subscription.user_id,
+        newly_available = newly_available.len(),
+        "Emailed newly available books."
+    );
+    Ok(())
+}
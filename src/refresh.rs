@@ -0,0 +1,302 @@
+use crate::app::{
+    get_goodreads_books, get_libby_availability, get_library_from_website_id, MatchTally,
+};
+use crate::cache::TtlCache;
+use crate::config::config;
+use crate::error_template::GoodreadsError;
+use crate::matching::MatchStrictness;
+use crate::types::{BookFormat, GoodreadsBook, LibbyBook, Library, DEFAULT_SHELF};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use leptos::ServerFnError;
+use rand::Rng;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{info, warn};
+
+// a shelf nobody has asked about for this long stops being refreshed
+const WATCH_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// the most shelves refreshed in the background, however many subscriptions there are
+const MAX_WATCHES: usize = 500;
+
+// each refresh waits up to this long before starting, so they don't all hit Goodreads at once
+const MAX_JITTER: Duration = Duration::from_secs(30);
+
+/// A shelf checked at a set of libraries, the way `/api/availability` is asked for it or an
+/// email subscription watches it. Built with `Watch::new` so the same request always makes the
+/// same key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Watch {
+    pub user_id: String,
+    pub shelves: Vec<String>,
+    pub library_website_ids: Vec<String>,
+    pub formats: Vec<BookFormat>,
+    pub languages: Vec<String>,
+    pub exclude_preorders: bool,
+    pub strictness: MatchStrictness,
+}
+
+impl Watch {
+    pub fn new(
+        user_id: &str,
+        shelves: Vec<String>,
+        library_website_ids: Vec<String>,
+        formats: Vec<BookFormat>,
+        languages: Vec<String>,
+        exclude_preorders: bool,
+        strictness: MatchStrictness,
+    ) -> Self {
+        let sorted = |mut list: Vec<String>| {
+            list.sort();
+            list.dedup();
+            list
+        };
+        // the same defaults the checks fall back to, so leaving a param out shares the key
+        let shelves = if shelves.is_empty() {
+            vec![DEFAULT_SHELF.to_string()]
+        } else {
+            sorted(shelves)
+        };
+        let formats = if formats.is_empty() {
+            BookFormat::DEFAULT.to_vec()
+        } else {
+            let mut formats = formats;
+            formats.sort_by_key(|format| format.label());
+            formats.dedup();
+            formats
+        };
+        Watch {
+            user_id: user_id.trim().to_string(),
+            shelves,
+            library_website_ids: sorted(library_website_ids),
+            formats,
+            languages: sorted(languages),
+            exclude_preorders,
+            strictness,
+        }
+    }
+}
+
+/// The books of a watched shelf as of the last refresh.
+#[derive(Clone, Debug)]
+pub struct Refreshed {
+    pub checked_at: DateTime<Utc>,
+    pub availability: Vec<LibbyBook>,
+}
+
+fn watches() -> &'static TtlCache<Watch, ()> {
+    static WATCHES: OnceLock<TtlCache<Watch, ()>> = OnceLock::new();
+    WATCHES.get_or_init(|| TtlCache::new(WATCH_TTL))
+}
+
+// kept for two rounds, so a slow or skipped round doesn't send everyone back to live checks
+fn results() -> &'static TtlCache<Watch, Refreshed> {
+    static RESULTS: OnceLock<TtlCache<Watch, Refreshed>> = OnceLock::new();
+    RESULTS.get_or_init(|| TtlCache::new(config().refresh_interval * 2))
+}
+
+/// Adds a shelf to the background refresh, or keeps it there for another week. Only for
+/// shelves someone registered, like a confirmed email subscription, since every watch costs
+/// Goodreads and Overdrive requests each round. Past MAX_WATCHES new shelves aren't added, and
+/// false is returned.
+pub fn watch(watch: &Watch) -> bool {
+    let watches = watches();
+    if watches.get(watch).is_none() && watches.keys().len() >= MAX_WATCHES {
+        warn!(
+            user_id = watch.user_id,
+            max_watches = MAX_WATCHES,
+            "Too many watched shelves, not refreshing another."
+        );
+        return false;
+    }
+    watches.insert(watch.clone(), ());
+    true
+}
+
+/// The last refresh's results, if it was recent enough to serve.
+pub fn cached(watch: &Watch) -> Option<Refreshed> {
+    results().get(watch)
+}
+
+/// Keeps results checked some other way (e.g. by a live API request) for `cached`.
+pub fn store(watch: &Watch, availability: Vec<LibbyBook>) {
+    results().insert(
+        watch.clone(),
+        Refreshed {
+            checked_at: Utc::now(),
+            availability,
+        },
+    );
+}
+
+/// Why a watched shelf couldn't be checked.
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error("{0}")]
+    Goodreads(GoodreadsError),
+    #[error("{0}")]
+    Upstream(String),
+}
+
+/// A watch's shelves read and its libraries resolved, ready for `check_books`.
+pub struct Prepared {
+    pub books: Vec<GoodreadsBook>,
+    pub libraries: Vec<Library>,
+}
+
+/// Reads the watch's Goodreads shelves and resolves its libraries.
+pub async fn prepare(watch: &Watch) -> Result<Prepared, CheckError> {
+    let books = get_goodreads_books(watch.user_id.clone(), watch.shelves.clone())
+        .await
+        .map_err(|err| match err {
+            ServerFnError::WrappedServerError(err) => CheckError::Goodreads(err),
+            err => CheckError::Upstream(err.to_string()),
+        })?;
+    let libraries = stream::iter(watch.library_website_ids.clone())
+        .then(get_library_from_website_id)
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|err| CheckError::Upstream(err.to_string()))?;
+    Ok(Prepared { books, libraries })
+}
+
+/// Checks every prepared book at every library, API_MAX_CONCURRENT_BOOKS at a time, yielding
+/// each result with the book's shelf position as soon as it's done. The search's match rate is
+/// recorded once the last book is in. `bypass_cache` skips recently cached Overdrive searches.
+pub fn check_books(
+    watch: &Watch,
+    prepared: Prepared,
+    bypass_cache: bool,
+) -> impl Stream<Item = (usize, Result<LibbyBook, String>)> {
+    let Prepared { books, libraries } = prepared;
+    let library_count = libraries.len();
+    let watch = watch.clone();
+    let tally = Arc::new(Mutex::new(MatchTally::default()));
+    let final_tally = tally.clone();
+    stream::iter(books.into_iter().enumerate())
+        .map(move |(position, book)| {
+            let checked = get_libby_availability(
+                book,
+                libraries.clone(),
+                watch.formats.clone(),
+                watch.languages.clone(),
+                watch.exclude_preorders,
+                watch.strictness,
+                None,
+                bypass_cache,
+                None,
+            );
+            async move { (position, checked.await.map_err(|err| err.to_string())) }
+        })
+        .buffer_unordered(config().api_max_concurrent_books)
+        .inspect(move |(_, result)| {
+            tally.lock().unwrap_or_else(|err| err.into_inner()).add(
+                result
+                    .as_ref()
+                    .ok()
+                    .map(|libby_book| libby_book.availability),
+            );
+        })
+        .chain(
+            stream::once(async move {
+                final_tally
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .record(library_count);
+            })
+            .filter_map(|_| async { None }),
+        )
+}
+
+/// Reads the shelves and checks every book at every library, keeping the results (in shelf
+/// order) for `cached`. Always searches Overdrive afresh, since what it keeps is served (and
+/// emailed about) as the latest answer until the next refresh.
+pub async fn check(watch: &Watch) -> Result<Vec<LibbyBook>, CheckError> {
+    let prepared = prepare(watch).await?;
+    let mut checked = check_books(watch, prepared, true)
+        .map(|(position, result)| result.map(|libby_book| (position, libby_book)))
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(CheckError::Upstream)?;
+    checked.sort_by_key(|(position, _)| *position);
+    let availability = checked
+        .into_iter()
+        .map(|(_, libby_book)| libby_book)
+        .collect::<Vec<_>>();
+    store(watch, availability.clone());
+    Ok(availability)
+}
+
+/// Re-checks every watched shelf each REFRESH_INTERVAL_SECS, REFRESH_MAX_CONCURRENT at a time,
+/// so the API and notifications can answer from `cached` instead of scraping on demand.
+pub fn spawn_refresher() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(config().refresh_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let due = watches().keys();
+            if due.is_empty() {
+                continue;
+            }
+            let watched = due.len();
+            let started = Instant::now();
+            let outcomes = stream::iter(due)
+                .map(|watch| async move {
+                    let jitter = rand::thread_rng().gen_range(Duration::ZERO..MAX_JITTER);
+                    tokio::time::sleep(jitter).await;
+                    match check(&watch).await {
+                        Ok(_) => true,
+                        Err(err) => {
+                            warn!(
+                                user_id = watch.user_id,
+                                error = %err,
+                                "Could not refresh shelf."
+                            );
+                            false
+                        }
+                    }
+                })
+                .buffer_unordered(config().refresh_max_concurrent)
+                .collect::<Vec<bool>>()
+                .await;
+            let refreshed = outcomes.iter().filter(|refreshed| **refreshed).count();
+            info!(
+                watched,
+                refreshed,
+                elapsed_secs = started.elapsed().as_secs(),
+                "Refreshed watched shelves."
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shelf(user_id: usize) -> Watch {
+        Watch::new(
+            &user_id.to_string(),
+            Vec::new(),
+            vec!["50".to_string()],
+            Vec::new(),
+            Vec::new(),
+            true,
+            MatchStrictness::default(),
+        )
+    }
+
+    #[test]
+    fn no_more_than_max_watches_are_refreshed() {
+        for user_id in 0..MAX_WATCHES {
+            assert!(watch(&shelf(user_id)));
+        }
+        assert!(!watch(&shelf(MAX_WATCHES)));
+        assert_eq!(watches().keys().len(), MAX_WATCHES);
+        // a shelf already watched can still be kept on
+        assert!(watch(&shelf(0)));
+    }
+}
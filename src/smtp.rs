@@ -0,0 +1,60 @@
+use crate::config::SmtpConfig;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// The port mail servers take implicit TLS on. Every other port is upgraded with STARTTLS.
+const IMPLICIT_TLS_PORT: u16 = 465;
+
+#[derive(Debug, Error)]
+pub enum SmtpError {
+    #[error("Invalid email address: {0}")]
+    InvalidAddress(#[from] lettre::address::AddressError),
+    #[error("Could not build the email: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP delivery failed: {0}")]
+    Transport(#[from] lettre::transport::smtp::Error),
+}
+
+static TRANSPORT: OnceLock<AsyncSmtpTransport<Tokio1Executor>> = OnceLock::new();
+
+// one pooled connection to the configured server, built on first send
+fn transport(smtp: &SmtpConfig) -> Result<&'static AsyncSmtpTransport<Tokio1Executor>, SmtpError> {
+    if let Some(transport) = TRANSPORT.get() {
+        return Ok(transport);
+    }
+    let builder = if smtp.port == IMPLICIT_TLS_PORT {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?
+    };
+    let transport = builder
+        .port(smtp.port)
+        .credentials(Credentials::new(
+            smtp.username.clone(),
+            smtp.password.clone(),
+        ))
+        .build();
+    Ok(TRANSPORT.get_or_init(|| transport))
+}
+
+/// Sends a plain-text email through the configured server, over implicit TLS on port 465 and
+/// STARTTLS on any other port.
+pub async fn send(smtp: &SmtpConfig, to: &str, subject: &str, body: &str) -> Result<(), SmtpError> {
+    let message = Message::builder()
+        .from(smtp.from.parse::<Mailbox>()?)
+        .to(Mailbox::new(None, to.parse::<Address>()?))
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())?;
+    transport(smtp)?.send(message).await?;
+    Ok(())
+}
+
+/// Whether an address is one we can send to.
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<Address>().is_ok()
+}
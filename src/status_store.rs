@@ -0,0 +1,144 @@
+use crate::config::config;
+use crate::types::{BookAvailability, BookStatus};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::{Mutex, OnceLock};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StatusStoreError {
+    #[error("Book status database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Book status task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+static CONNECTION: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+// opened on first use, STATUS_STORE_PATH when that's set and otherwise a database that only
+// lives in memory. Only call from a blocking task.
+fn connection() -> Result<&'static Mutex<Connection>, StatusStoreError> {
+    if let Some(connection) = CONNECTION.get() {
+        return Ok(connection);
+    }
+    let connection = match &config().status_store_path {
+        Some(path) => Connection::open(path)?,
+        None => Connection::open_in_memory()?,
+    };
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS book_statuses (
+            user_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            author TEXT NOT NULL,
+            library TEXT NOT NULL,
+            status TEXT NOT NULL,
+            PRIMARY KEY (user_id, title, author, library)
+        )",
+    )?;
+    Ok(CONNECTION.get_or_init(|| Mutex::new(connection)))
+}
+
+// how a status is kept in the status column
+fn column(status: BookAvailability) -> &'static str {
+    match status {
+        BookAvailability::Available => "available",
+        BookAvailability::Holdable => "holdable",
+        BookAvailability::NotOwned => "not_owned",
+        BookAvailability::Unknown => "unknown",
+    }
+}
+
+/// Remembers each book's status at each library for an email subscription, returning the ones
+/// that just became available after being holdable or not owned last time. A book seen for the
+/// first time is never "new". Kept in SQLite, one row per subscription, book and library,
+/// written to STATUS_STORE_PATH when that's set so it survives restarts. Only the server's own
+/// subscriptions are recorded, the browser keeps a user's search history itself.
+pub async fn record(
+    user_id: &str,
+    book_statuses: &[BookStatus],
+) -> Result<Vec<BookStatus>, StatusStoreError> {
+    let user_id = user_id.to_string();
+    let book_statuses = book_statuses.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut connection = connection()?.lock().unwrap_or_else(|err| err.into_inner());
+        let transaction = connection.transaction()?;
+        let mut newly_available = Vec::new();
+        {
+            let mut previous_status = transaction.prepare_cached(
+                "SELECT status FROM book_statuses
+                 WHERE user_id = ?1 AND title = ?2 AND author = ?3 AND library = ?4",
+            )?;
+            let mut upsert = transaction.prepare_cached(
+                "INSERT INTO book_statuses (user_id, title, author, library, status)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (user_id, title, author, library)
+                 DO UPDATE SET status = excluded.status",
+            )?;
+            for book_status in book_statuses {
+                let previous = previous_status
+                    .query_row(
+                        params![
+                            user_id,
+                            book_status.title,
+                            book_status.author,
+                            book_status.library
+                        ],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()?;
+                upsert.execute(params![
+                    user_id,
+                    book_status.title,
+                    book_status.author,
+                    book_status.library,
+                    column(book_status.status)
+                ])?;
+                let previous = previous.and_then(|previous| {
+                    BookAvailability::ALL
+                        .into_iter()
+                        .find(|status| column(*status) == previous)
+                });
+                if book_status.status.became_available(previous) {
+                    newly_available.push(book_status);
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(newly_available)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book_status(status: BookAvailability) -> BookStatus {
+        BookStatus {
+            title: "The Night Circus".to_string(),
+            author: "Erin Morgenstern".to_string(),
+            library: "Hawaii State Public Library System".to_string(),
+            status,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_books_that_were_unavailable_are_new() {
+        let first = record("test-user", &[book_status(BookAvailability::Available)])
+            .await
+            .unwrap();
+        assert!(first.is_empty());
+
+        record("test-user", &[book_status(BookAvailability::Holdable)])
+            .await
+            .unwrap();
+        let newly_available = record("test-user", &[book_status(BookAvailability::Available)])
+            .await
+            .unwrap();
+        assert_eq!(newly_available.len(), 1);
+
+        let again = record("test-user", &[book_status(BookAvailability::Available)])
+            .await
+            .unwrap();
+        assert!(again.is_empty());
+    }
+}
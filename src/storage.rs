@@ -0,0 +1,69 @@
+use leptos::window;
+
+/// Comma-separated website ids of the libraries picked last time, e.g. "50,34550,315".
+pub const LIBRARIES_KEY: &str = "libbyreads.libraries";
+
+/// Pickup branch per library system, as a JSON object, e.g. {"Hawaii State Public Library System": "Hawaii Kai Library"}.
+pub const BRANCHES_KEY: &str = "libbyreads.branches";
+
+/// Comma-separated website ids of the selected libraries that offer Hoopla, e.g. "50".
+pub const HOOPLA_LIBRARIES_KEY: &str = "libbyreads.hoopla_libraries";
+
+/// The Goodreads user id whose shelves loaded last time.
+pub const USER_ID_KEY: &str = "libbyreads.user_id";
+
+/// Comma-separated Overdrive language codes searched in, e.g. "en,es". Empty for any language.
+pub const LANGUAGES_KEY: &str = "libbyreads.languages";
+
+/// Whether pre-order titles are left out of searches, "true" or "false".
+pub const EXCLUDE_PREORDERS_KEY: &str = "libbyreads.exclude_preorders";
+
+/// How many books the browser checks at the same time, e.g. "5".
+pub const CHECK_CONCURRENCY_KEY: &str = "libbyreads.check_concurrency";
+
+/// The book table's sort column and direction, e.g. "avg_rating:desc".
+pub const SORT_KEY: &str = "libbyreads.sort";
+
+/// How roomy the book table is, "comfortable" or "compact".
+pub const DENSITY_KEY: &str = "libbyreads.density";
+
+/// Whether the book table shows its Publisher and Year columns, "true" or "false".
+pub const PUBLICATION_COLUMNS_KEY: &str = "libbyreads.publication_columns";
+
+/// Each user's book statuses after their last search here, as JSON keyed by user id and then
+/// "title|author|library", so books that became available since get a "NEW!" badge.
+pub const BOOK_STATUSES_KEY: &str = "libbyreads.book_statuses";
+
+/// Overdrive title ids books were pinned to, as JSON keyed by Goodreads user id and then
+/// "title|author", e.g. {"12345": {"Dune|Frank Herbert": "1234567"}}.
+pub const PINS_KEY: &str = "libbyreads.pins";
+
+/// The last finished search's results, as JSON, with what they were for and when they were checked.
+pub const AVAILABILITY_KEY: &str = "libbyreads.availability";
+
+/// Reads a value this browser saved earlier. Only call from effects or event handlers,
+/// there is no `window` while rendering on the server.
+pub fn load(key: &str) -> Option<String> {
+    window()
+        .local_storage()
+        .ok()
+        .flatten()?
+        .get_item(key)
+        .ok()
+        .flatten()
+}
+
+/// Saves a value for the next visit. Storage can be disabled (e.g. private browsing), in
+/// which case this quietly does nothing.
+pub fn save(key: &str, value: &str) {
+    if let Ok(Some(storage)) = window().local_storage() {
+        let _ = storage.set_item(key, value);
+    }
+}
+
+/// Forgets a saved value, e.g. a user id that no longer resolves to a readable profile.
+pub fn remove(key: &str) {
+    if let Ok(Some(storage)) = window().local_storage() {
+        let _ = storage.remove_item(key);
+    }
+}
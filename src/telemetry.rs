@@ -0,0 +1,138 @@
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use opentelemetry::trace::{TraceContextExt, Tracer as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::trace::Tracer;
+use std::fmt::Debug;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Turns `tracing` spans (e.g. from `#[tracing::instrument]`) into OpenTelemetry spans, with
+/// the span's fields as attributes, so a request's spans show up as one trace in Honeycomb.
+pub struct OtelSpanLayer {
+    tracer: Tracer,
+}
+
+impl OtelSpanLayer {
+    pub fn new(tracer: Tracer) -> Self {
+        OtelSpanLayer { tracer }
+    }
+}
+
+impl<S> Layer<S> for OtelSpanLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        // nest under the enclosing span's trace, or start a new trace
+        let parent_cx = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Context>().cloned())
+            .unwrap_or_default();
+        let mut attributes = AttributeVisitor(Vec::new());
+        attrs.record(&mut attributes);
+        let otel_span = self
+            .tracer
+            .span_builder(attrs.metadata().name())
+            .with_attributes(attributes.0)
+            .start_with_context(&self.tracer, &parent_cx);
+        span.extensions_mut().insert(parent_cx.with_span(otel_span));
+    }
+
+    // fields declared `Empty` and filled in later, e.g. a book count
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut attributes = AttributeVisitor(Vec::new());
+        values.record(&mut attributes);
+        let extensions = span.extensions();
+        if let Some(cx) = extensions.get::<Context>() {
+            cx.span().set_attributes(attributes.0);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let cx = span.extensions_mut().remove::<Context>();
+        if let Some(cx) = cx {
+            cx.span().end();
+        }
+    }
+}
+
+struct AttributeVisitor(Vec<KeyValue>);
+
+impl Visit for AttributeVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push(KeyValue::new(field.name(), value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push(KeyValue::new(field.name(), value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push(KeyValue::new(field.name(), value as i64));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push(KeyValue::new(field.name(), value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .push(KeyValue::new(field.name(), format!("{:?}", value)));
+    }
+}
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Installs the Prometheus recorder on first use, and returns the handle `/metrics` renders.
+/// Until it's installed, e.g. in the CLI, counters and histograms record nothing.
+pub fn prometheus() -> &'static PrometheusHandle {
+    PROMETHEUS.get_or_init(|| {
+        PrometheusBuilder::new()
+            .set_buckets(&LATENCY_BUCKETS)
+            .expect("latency buckets are not empty")
+            .install_recorder()
+            .expect("failed to install the Prometheus recorder")
+    })
+}
+
+/// Times a server function from creation until it's dropped, so every return path counts.
+pub struct ServerFnTimer {
+    server_fn: &'static str,
+    start: Instant,
+}
+
+impl ServerFnTimer {
+    pub fn start(server_fn: &'static str) -> Self {
+        ServerFnTimer {
+            server_fn,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ServerFnTimer {
+    fn drop(&mut self) {
+        metrics::histogram!(
+            "libbyreads_server_fn_duration_seconds",
+            "server_fn" => self.server_fn
+        )
+        .record(self.start.elapsed());
+    }
+}
@@ -0,0 +1,317 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// Shown for books without a cover, or whose cover image fails to load. Bundled from `public/`.
+pub const COVER_PLACEHOLDER: &str = "/cover-placeholder.svg";
+
+/// The Goodreads shelf we read when none is chosen.
+pub const DEFAULT_SHELF: &str = "to-read";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+
+pub enum BookAvailability {
+    Available,
+    Holdable,
+    NotOwned,
+    // the lookup failed or timed out somewhere, so we can't say it isn't owned
+    Unknown,
+}
+
+impl BookAvailability {
+    pub const ALL: [BookAvailability; 4] = [
+        BookAvailability::Available,
+        BookAvailability::Holdable,
+        BookAvailability::NotOwned,
+        BookAvailability::Unknown,
+    ];
+
+    /// Whether a book just became available after being holdable or not owned the previous
+    /// time it was seen. A book seen for the first time never has.
+    pub fn became_available(self, previous: Option<BookAvailability>) -> bool {
+        self == BookAvailability::Available
+            && matches!(
+                previous,
+                Some(BookAvailability::Holdable | BookAvailability::NotOwned)
+            )
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BookAvailability::Available => "Available",
+            BookAvailability::Holdable => "Holdable",
+            BookAvailability::NotOwned => "Not Owned",
+            BookAvailability::Unknown => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+
+pub struct GoodreadsBook {
+    pub cover: String,
+    pub title: String,
+    // the first listed author, used for display and searching
+    pub author: String,
+    // every listed author, including `author`, for matching co-authored books
+    pub authors: Vec<String>,
+    // e.g. "Shades of Magic, #1", shown next to the title on Goodreads
+    pub series: Option<String>,
+    pub date_added: Option<NaiveDate>,
+    pub avg_rating: Option<f32>,
+    pub isbn: Option<String>,
+    // when the book first came out, e.g. 2015, whichever edition is on the shelf
+    #[serde(default)]
+    pub publication_year: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum BookFormat {
+    Ebook,
+    Audiobook,
+    Magazine,
+    // comics and graphic novels, which Overdrive lends as ebooks filed under a comics subject
+    Comic,
+}
+
+impl BookFormat {
+    pub const ALL: [BookFormat; 4] = [
+        BookFormat::Ebook,
+        BookFormat::Audiobook,
+        BookFormat::Magazine,
+        BookFormat::Comic,
+    ];
+
+    /// What we search when nothing is selected.
+    pub const DEFAULT: [BookFormat; 1] = [BookFormat::Audiobook];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BookFormat::Ebook => "Ebook",
+            BookFormat::Audiobook => "Audiobook",
+            BookFormat::Magazine => "Magazine",
+            BookFormat::Comic => "Comic",
+        }
+    }
+
+    /// The Overdrive `format=` values that make up this format, including the `-provisional`
+    /// ones Overdrive files pre-orders under.
+    pub fn overdrive_formats(&self) -> &'static str {
+        match self {
+            BookFormat::Ebook => "ebook-overdrive,ebook-media-do,ebook-overdrive-provisional",
+            BookFormat::Audiobook => "audiobook-overdrive,audiobook-overdrive-provisional",
+            BookFormat::Magazine => "magazine-overdrive",
+            BookFormat::Comic => "ebook-overdrive,ebook-media-do",
+        }
+    }
+
+    /// The `type.id` Overdrive reports on media items of this format, also used in share URLs.
+    /// Comics are reported as "ebook", "comic" only ever appears in our own URLs.
+    pub fn overdrive_type_id(&self) -> &'static str {
+        match self {
+            BookFormat::Ebook => "ebook",
+            BookFormat::Audiobook => "audiobook",
+            BookFormat::Magazine => "magazine",
+            BookFormat::Comic => "comic",
+        }
+    }
+
+    pub fn from_overdrive_type_id(type_id: &str) -> Option<BookFormat> {
+        BookFormat::ALL
+            .into_iter()
+            .find(|format| format.overdrive_type_id() == type_id)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LibbyLibraryBook {
+    pub cover: String,
+    pub title: String,
+    pub author: String,
+    // the library system this result came from, e.g. Hawaii State Public Library System
+    pub library: String,
+    pub format: BookFormat,
+    pub is_available: bool,
+    pub is_holdable: bool,
+    // we don't track is_owned directly, because we can infer it from is_available and is_holdable
+    // the title's own Libby page (borrow / place hold) when we know its id, else the search
+    pub libby_search_url: String,
+    // Overdrive's id for the matched title, e.g. "1234567"
+    pub title_id: Option<String>,
+    // Overdrive's hold queue estimate, only reported for some holdable titles
+    pub estimated_wait_days: Option<i64>,
+    pub holds_count: Option<i64>,
+    pub owned_copies: Option<i64>,
+    pub available_copies: Option<i64>,
+    // audiobooks only: who reads it, e.g. "Jim Dale", and how long it runs, e.g. "10:12:34"
+    #[serde(default)]
+    pub narrator: Option<String>,
+    #[serde(default)]
+    pub duration: Option<String>,
+    // this edition's publisher and the year Overdrive says it was published
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub publication_year: Option<i32>,
+}
+
+/// Availability of a book in one format, summarized across all selected libraries.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FormatAvailability {
+    pub is_available: bool,
+    pub is_holdable: bool,
+    pub libby_search_url: String,
+    // shortest estimated wait among the libraries it can be held at
+    pub estimated_wait_days: Option<i64>,
+    // most copies free at any one library that has it available
+    pub available_copies: Option<i64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LibbyBook {
+    pub cover: String,
+    pub title: String,
+    pub author: String,
+    // the overall status at the best of the selected libraries
+    pub availability: BookAvailability,
+    pub is_available: bool,
+    pub is_holdable: bool,
+    // we don't track is_owned directly, because we can infer it from is_available and is_holdable
+    pub libby_search_url: String,
+    pub estimated_wait_days: Option<i64>,
+    pub available_copies: Option<i64>,
+    // libraries that couldn't give an answer, e.g. because Overdrive kept rate limiting us.
+    // when nothing else was found the book is unknown rather than not owned
+    pub errored_libraries: Vec<String>,
+    // None when the format wasn't searched
+    pub ebook: Option<FormatAvailability>,
+    pub audiobook: Option<FormatAvailability>,
+    pub magazine: Option<FormatAvailability>,
+    #[serde(default)]
+    pub comic: Option<FormatAvailability>,
+    pub library_books: Vec<LibbyLibraryBook>,
+    // from the first library result that has them, to tell editions apart
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub publication_year: Option<i32>,
+    // when the libraries were asked. missing from results saved before it was recorded
+    #[serde(default)]
+    pub checked_at: Option<DateTime<Utc>>,
+    // the Overdrive title id the user pinned this book to, looked up instead of searched for
+    #[serde(default)]
+    pub pinned_title_id: Option<String>,
+}
+impl LibbyBook {
+    /// Whether this result belongs to the given shelf book.
+    pub fn is_for(&self, book: &GoodreadsBook) -> bool {
+        self.title == book.title && self.author == book.author
+    }
+
+    /// The result for a book whose whole lookup failed, e.g. the server function timed out.
+    pub fn unknown(book: &GoodreadsBook) -> LibbyBook {
+        LibbyBook {
+            cover: book.cover.to_string(),
+            title: book.title.to_string(),
+            author: book.author.to_string(),
+            availability: BookAvailability::Unknown,
+            is_available: false,
+            is_holdable: false,
+            libby_search_url: String::new(),
+            estimated_wait_days: None,
+            available_copies: None,
+            errored_libraries: Vec::new(),
+            ebook: None,
+            audiobook: None,
+            magazine: None,
+            comic: None,
+            library_books: Vec::new(),
+            checked_at: None,
+            pinned_title_id: None,
+            publisher: None,
+            publication_year: None,
+        }
+    }
+
+    /// This book's status at each library that answered, in any of the searched formats.
+    pub fn library_statuses(&self) -> Vec<BookStatus> {
+        let mut statuses = Vec::<BookStatus>::new();
+        for library_book in &self.library_books {
+            let status = if library_book.is_available {
+                BookAvailability::Available
+            } else if library_book.is_holdable {
+                BookAvailability::Holdable
+            } else {
+                BookAvailability::NotOwned
+            };
+            let rank =
+                |status: BookAvailability| BookAvailability::ALL.iter().position(|s| *s == status);
+            match statuses
+                .iter_mut()
+                .find(|known| known.library == library_book.library)
+            {
+                // the best format wins, e.g. an available ebook beats a holdable audiobook
+                Some(known) if rank(status) < rank(known.status) => known.status = status,
+                Some(_) => {}
+                None => statuses.push(BookStatus {
+                    title: self.title.clone(),
+                    author: self.author.clone(),
+                    library: library_book.library.clone(),
+                    status,
+                }),
+            }
+        }
+        statuses
+    }
+
+    /// Availability in a single format, None if that format wasn't searched.
+    pub fn format(&self, format: BookFormat) -> Option<&FormatAvailability> {
+        match format {
+            BookFormat::Ebook => self.ebook.as_ref(),
+            BookFormat::Audiobook => self.audiobook.as_ref(),
+            BookFormat::Magazine => self.magazine.as_ref(),
+            BookFormat::Comic => self.comic.as_ref(),
+        }
+    }
+}
+
+/// A book's status at one library, as remembered between searches.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct BookStatus {
+    pub title: String,
+    pub author: String,
+    pub library: String,
+    pub status: BookAvailability,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SearchLibrary {
+    pub system_name: String,    // Hawaii State Public Library System
+    pub website_id: String,     // 50
+    pub fulfillment_id: String, // hawaii
+    pub name: String,           // Hawaii Kai Library
+    pub location: String,       // Honolulu, HI
+    pub branch_count: i32,
+}
+/// One branch of a library system, as listed by the Libby autocomplete.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct LibraryBranch {
+    pub name: String,     // Hawaii Kai Library
+    pub location: String, // Honolulu, HI
+}
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Library {
+    pub search_library: SearchLibrary,
+
+    pub system_id: String,          // hawaii
+    pub libby_base_url: String,     // https://libbyapp.com/library/hawaii
+    pub overdrive_base_url: String, // https://thunder.api.overdrive.com/v2/libraries/hawaii
+}
+
+/// A book's title on Hoopla, which lends instantly (no holds) to members of a library that
+/// subscribes to it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct HooplaTitle {
+    pub title: String,
+    // e.g. "EBOOK", "AUDIOBOOK", "COMIC"
+    pub kind: String,
+    pub hoopla_url: String,
+}